@@ -0,0 +1,64 @@
+use std::collections::{BTreeMap, HashSet};
+use std::io;
+use std::net::{IpAddr, Ipv6Addr};
+
+use if_addrs::{get_if_addrs, IfAddr};
+use sysinfo::Networks;
+
+use public_lib::message::{AdapterAddress, IpAddresses};
+
+/// Collects the addresses of every network adapter, ordered by adapter name so that
+/// two snapshots of an unchanged system compare equal.
+pub fn collect_adapter_addresses() -> io::Result<Vec<IpAddresses>> {
+    let network_interfaces = get_if_addrs()?;
+    let temporary_addresses = temporary_ipv6_addresses();
+
+    let mut ip_to_name_map: BTreeMap<String, IpAddresses> = BTreeMap::new();
+
+    for interface in network_interfaces.iter() {
+        let ip = interface.ip();
+        let prefix_len = match &interface.addr {
+            IfAddr::V4(v4) => u32::from(v4.netmask).count_ones(),
+            IfAddr::V6(v6) => u128::from(v6.netmask).count_ones(),
+        } as u8;
+        let temporary = matches!(ip, IpAddr::V6(v6) if temporary_addresses.contains(&v6));
+        let address = AdapterAddress::new(ip, Some(prefix_len), temporary);
+        ip_to_name_map.entry(interface.name.clone())
+            .or_insert_with(|| IpAddresses::empty(interface.name.clone()))
+            .append_address(address);
+    }
+
+    let networks = Networks::new_with_refreshed_list();
+    for (name, network) in networks.list() {
+        let mac_address = network.mac_address();
+        if let Some(ip_addresses) = ip_to_name_map.get_mut(name) {
+            ip_addresses.mac = (!mac_address.is_unspecified()).then(|| mac_address.to_string());
+        }
+    }
+
+    Ok(ip_to_name_map.into_values().collect())
+}
+
+/// IPv6 addresses flagged as temporary (RFC 4941 privacy extensions) by the kernel,
+/// only available on Linux through `/proc/net/if_inet6`
+#[cfg(target_os = "linux")]
+fn temporary_ipv6_addresses() -> HashSet<Ipv6Addr> {
+    const IFA_F_TEMPORARY: u32 = 0x01;
+    let content = std::fs::read_to_string("/proc/net/if_inet6").unwrap_or_else(|e| {
+        tracing::debug!("Failed to read /proc/net/if_inet6: {}", e);
+        String::new()
+    });
+    content.lines()
+        .filter_map(|line| {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            let address = u128::from_str_radix(fields.first()?, 16).ok()?;
+            let flags = u32::from_str_radix(fields.get(4)?, 16).ok()?;
+            (flags & IFA_F_TEMPORARY != 0).then(|| Ipv6Addr::from(address))
+        })
+        .collect()
+}
+
+#[cfg(not(target_os = "linux"))]
+fn temporary_ipv6_addresses() -> HashSet<Ipv6Addr> {
+    HashSet::new()
+}
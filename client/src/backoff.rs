@@ -0,0 +1,38 @@
+use std::time::Duration;
+
+use rand::Rng;
+
+/// Exponential backoff with "equal jitter": each delay is picked uniformly from
+/// the upper half of the current exponential step, capped by `max_delay`.
+pub struct Backoff {
+    initial_delay: Duration,
+    max_delay: Duration,
+    attempt: u32,
+}
+
+impl Backoff {
+    pub fn new(initial_delay: Duration, max_delay: Duration) -> Backoff {
+        Backoff {
+            initial_delay,
+            max_delay: max_delay.max(initial_delay),
+            attempt: 0,
+        }
+    }
+
+    pub fn attempt(&self) -> u32 {
+        self.attempt
+    }
+
+    pub fn reset(&mut self) {
+        self.attempt = 0;
+    }
+
+    pub fn next_delay(&mut self) -> Duration {
+        let step = self.initial_delay
+            .saturating_mul(2u32.saturating_pow(self.attempt))
+            .min(self.max_delay);
+        self.attempt = self.attempt.saturating_add(1);
+        let half = step / 2;
+        half + rand::thread_rng().gen_range(Duration::ZERO..=half)
+    }
+}
@@ -0,0 +1,15 @@
+use sysinfo::System;
+
+use public_lib::message::HostInfo;
+
+/// Collects the description of this machine sent to the server along with the addresses
+pub fn collect_host_info() -> HostInfo {
+    HostInfo {
+        hostname: System::host_name(),
+        os_version: System::long_os_version(),
+        kernel_version: System::kernel_version(),
+        arch: std::env::consts::ARCH.to_string(),
+        uptime: System::uptime(),
+        client_version: env!("CARGO_PKG_VERSION").to_string(),
+    }
+}
@@ -1,39 +1,102 @@
-use std::collections::HashMap;
+use std::net::IpAddr;
+use std::path::PathBuf;
 use std::str::FromStr;
+use std::time::Duration;
 
 use base64::Engine;
 use base64::prelude::BASE64_STANDARD;
-use clap::{arg, Parser};
+use clap::{ArgGroup, Parser};
 use futures_util::{SinkExt, StreamExt};
-use local_ip_address::list_afinet_netifas;
 use time::UtcOffset;
 use tokio::fs::OpenOptions;
 use tokio::io;
 use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::time::Instant;
 use tokio_tungstenite::connect_async;
 use tokio_tungstenite::tungstenite::http::Uri;
-use tracing::{debug, error, info};
+use tokio_tungstenite::tungstenite::Message;
+use tracing::{debug, error, info, warn};
 use uuid::Uuid;
 
-use public_lib::message::{IpAddresses, MessagePack};
+use public_lib::config::{parse_args_with_config_file, read_secret_file};
+use public_lib::message::{Capability, Encoding, HostInfo, MessagePack, negotiate_protocol_version, PROTOCOL_VERSION};
 use public_lib::tracing::{tracing_timer, TracingLogLevel};
 
+use crate::addresses::collect_adapter_addresses;
+use crate::backoff::Backoff;
+use crate::host::collect_host_info;
+use crate::stun::PublicAddressMonitor;
+use crate::watcher::AddressWatcher;
+
+mod addresses;
+mod backoff;
+mod host;
+mod stun;
+mod watcher;
+
 #[derive(Parser, Debug)]
 #[command(name = "Host Exposer Client")]
 #[command(author, version, about)]
+#[command(group(ArgGroup::new("credential").required(true).args(["pwd", "pwd_file", "token", "token_file"])))]
 struct Args {
+    /// TOML configuration file with any of the options below, overridden by environment variables and arguments
+    #[arg(long, env = "HOST_EXPOSER_CLIENT_CONFIG", value_name = "FILE")]
+    config: Option<PathBuf>,
     /// Target server websocket URI
-    #[arg(short, long, value_parser = parse_uri, value_name = "URI")]
+    #[arg(short, long, env = "HOST_EXPOSER_CLIENT_TARGET_URI", value_parser = parse_uri, value_name = "URI")]
     target_uri: Uri,
-    /// Password for the server
-    #[arg(short, long, value_name = "PASSWORD")]
-    pwd: String,
+    /// Password for the server, only accepted by servers allowing password clients
+    #[arg(short, long, env = "HOST_EXPOSER_CLIENT_PWD", value_name = "PASSWORD")]
+    pwd: Option<String>,
+    /// File containing the password for the server
+    #[arg(long, env = "HOST_EXPOSER_CLIENT_PWD_FILE", value_name = "FILE")]
+    pwd_file: Option<PathBuf>,
+    /// Enrollment token issued by the server for this client
+    #[arg(long, env = "HOST_EXPOSER_CLIENT_TOKEN", value_name = "TOKEN")]
+    token: Option<String>,
+    /// File containing the enrollment token
+    #[arg(long, env = "HOST_EXPOSER_CLIENT_TOKEN_FILE", value_name = "FILE")]
+    token_file: Option<PathBuf>,
+    /// Name to list this client under, instead of the one given on the server
+    #[arg(long, env = "HOST_EXPOSER_CLIENT_NAME", value_name = "NAME")]
+    name: Option<String>,
+    /// Tags of this client, replacing the ones given on the server
+    #[arg(long, env = "HOST_EXPOSER_CLIENT_TAG", value_delimiter = ',', value_name = "TAG")]
+    tag: Vec<String>,
     /// Maximum Log level
-    #[arg(long, ignore_case = true, value_enum, default_value_t)]
+    #[arg(long, env = "HOST_EXPOSER_CLIENT_MAX_LOG_LEVEL", ignore_case = true, value_enum, default_value_t)]
     max_log_level: TracingLogLevel,
     /// Default UTC offset if the application cannot determine the local time zone
-    #[arg(long, default_value = "+00:00", value_parser = public_lib::times::parse_utc_offset, value_name = "UTC_OFFSET")]
+    #[arg(long, env = "HOST_EXPOSER_CLIENT_DEFAULT_OFFSET", default_value = "+00:00", value_parser = public_lib::times::parse_utc_offset, value_name = "UTC_OFFSET")]
     default_offset: UtcOffset,
+    /// Delay before the first reconnection attempt after the connection to the server is lost
+    #[arg(long, env = "HOST_EXPOSER_CLIENT_INITIAL_RETRY_DELAY", default_value = "1", value_name = "SECONDS")]
+    initial_retry_delay: u64,
+    /// Upper bound of the exponentially growing delay between reconnection attempts
+    #[arg(long, env = "HOST_EXPOSER_CLIENT_MAX_RETRY_DELAY", default_value = "300", value_name = "SECONDS")]
+    max_retry_delay: u64,
+    /// Interval of checking the network addresses for changes, in addition to the netlink notifications on Linux
+    #[arg(long, env = "HOST_EXPOSER_CLIENT_WATCH_INTERVAL", default_value = "30", value_parser = clap::value_parser!(u64).range(1..), value_name = "SECONDS")]
+    watch_interval: u64,
+    /// Interval of the websocket pings sent to the server
+    #[arg(long, env = "HOST_EXPOSER_CLIENT_HEARTBEAT_INTERVAL", default_value = "30", value_parser = clap::value_parser!(u64).range(1..), value_name = "SECONDS")]
+    heartbeat_interval: u64,
+    /// Reconnect if the server sent nothing, not even a pong, for this long
+    #[arg(long, env = "HOST_EXPOSER_CLIENT_HEARTBEAT_TIMEOUT", default_value = "90", value_parser = clap::value_parser!(u64).range(1..), value_name = "SECONDS")]
+    heartbeat_timeout: u64,
+    /// STUN servers to discover the public addresses of this host with, e.g. stun.l.google.com:19302, disabled if empty
+    #[arg(long, env = "HOST_EXPOSER_CLIENT_STUN_SERVER", value_delimiter = ',', value_name = "HOST:PORT")]
+    stun_server: Vec<String>,
+    /// Send the messages as compact binary MessagePack instead of JSON if the server supports it,
+    /// saving traffic on metered links
+    #[arg(long, env = "HOST_EXPOSER_CLIENT_BINARY_ENCODING")]
+    binary_encoding: bool,
+    /// Maximum time to wait for the answer of each STUN server
+    #[arg(long, env = "HOST_EXPOSER_CLIENT_STUN_TIMEOUT", default_value = "3", value_parser = clap::value_parser!(u64).range(1..), value_name = "SECONDS")]
+    stun_timeout: u64,
+    /// Interval of the public address discovery through STUN, which also runs whenever the local addresses change
+    #[arg(long, env = "HOST_EXPOSER_CLIENT_STUN_INTERVAL", default_value = "300", value_parser = clap::value_parser!(u64).range(1..), value_name = "SECONDS")]
+    stun_interval: u64,
 }
 
 fn parse_uri(s: &str) -> Result<Uri, String> {
@@ -53,6 +116,7 @@ async fn get_self_id() -> io::Result<Uuid> {
         .read(true)
         .write(true)
         .create(true)
+        .truncate(false)
         .open(".exposer_id")
         .await?;
     let mut buffer = String::new();
@@ -71,80 +135,222 @@ async fn get_self_id() -> io::Result<Uuid> {
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let args = Args::parse();
+    let mut args: Args = parse_args_with_config_file();
+    if let Some(pwd_file) = args.pwd_file.take() {
+        args.pwd.get_or_insert(read_secret_file(pwd_file)?);
+    }
+    if let Some(token_file) = args.token_file.take() {
+        args.token.get_or_insert(read_secret_file(token_file)?);
+    }
     tracing_subscriber::fmt()
         .with_timer(tracing_timer(args.default_offset))
-        .with_max_level(args.max_log_level).init();
-    let (ws_stream, _) = connect_async(&args.target_uri).await?;
-    info!("Establishing connection to server {}", &args.target_uri);
+        .with_max_level(args.max_log_level.clone()).init();
     let self_id = get_self_id().await?;
     info!("Self id: {}", &self_id);
+    let mut public_address_monitor = (!args.stun_server.is_empty()).then(|| PublicAddressMonitor::spawn(
+        args.stun_server.clone(),
+        Duration::from_secs(args.stun_timeout),
+        Duration::from_secs(args.stun_interval),
+    ));
+    let mut backoff = Backoff::new(
+        Duration::from_secs(args.initial_retry_delay),
+        Duration::from_secs(args.max_retry_delay),
+    );
+    loop {
+        info!("Connecting to server {} (attempt {})", &args.target_uri, backoff.attempt() + 1);
+        match run_session(&args, &self_id, &mut public_address_monitor, &mut backoff).await {
+            Ok(()) => warn!("Connection to server {} closed", &args.target_uri),
+            Err(e) => error!("Connection to server {} failed: {}", &args.target_uri, e),
+        }
+        let delay = backoff.next_delay();
+        info!("Reconnecting to server {} in {:.1}s", &args.target_uri, delay.as_secs_f64());
+        tokio::time::sleep(delay).await;
+    }
+}
+
+fn collect_public_addresses(public_address_monitor: &mut Option<PublicAddressMonitor>, server_capabilities: &[Capability]) -> Vec<IpAddr> {
+    match public_address_monitor {
+        Some(monitor) if server_capabilities.contains(&Capability::PublicAddresses) => monitor.latest(),
+        _ => Vec::new(),
+    }
+}
+
+/// Wakes up when the local addresses may have changed, asking for the public addresses to be
+/// discovered again as well, or when other public addresses were discovered
+async fn addresses_changed(watcher: &mut AddressWatcher, public_address_monitor: &mut Option<PublicAddressMonitor>) {
+    match public_address_monitor {
+        Some(monitor) => tokio::select! {
+            _ = watcher.changed() => monitor.refresh(),
+            _ = monitor.changed() => {}
+        },
+        None => watcher.changed().await,
+    }
+}
+
+/// Advertised to the server in the handshake
+fn client_capabilities(args: &Args) -> Vec<Capability> {
+    let mut capabilities = vec![Capability::AddrUpdate, Capability::HostInfo, Capability::PublicAddresses];
+    if args.binary_encoding {
+        capabilities.push(Capability::MessagePackEncoding);
+    }
+    capabilities
+}
+
+/// Host information is only sent to servers taking it
+fn host_info_for(server_capabilities: &[Capability]) -> Option<HostInfo> {
+    server_capabilities.contains(&Capability::HostInfo).then(collect_host_info)
+}
+
+/// Connects to the server and serves its requests until the connection is closed,
+/// the backoff is reset once the server acknowledged this client.
+async fn run_session(
+    args: &Args,
+    self_id: &Uuid,
+    public_address_monitor: &mut Option<PublicAddressMonitor>,
+    backoff: &mut Backoff,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let (ws_stream, _) = connect_async(&args.target_uri).await?;
+    info!("Establishing connection to server {}", &args.target_uri);
     let (mut ws_tx, mut ws_rx) = ws_stream.split();
+    let own_capabilities = client_capabilities(args);
     ws_tx.send(
-        MessagePack::Establish { id: self_id, password: BASE64_STANDARD.encode(args.pwd) }.to_message()
+        MessagePack::Establish {
+            id: *self_id,
+            protocol_version: PROTOCOL_VERSION,
+            capabilities: own_capabilities.clone(),
+            password: args.pwd.as_ref().map(|pwd| BASE64_STANDARD.encode(pwd)).unwrap_or_default(),
+            token: args.token.clone(),
+            name: args.name.clone(),
+            tags: args.tag.clone(),
+        }.to_message()
     ).await?;
-    if let Some(Ok(msg)) = ws_rx.next().await {
-        let text = msg.to_text()?;
-        match MessagePack::from_str(text)? {
-            MessagePack::Acknowledge => {}
-            MessagePack::Error { message } => {
-                error!("Received error message: {}", message);
-                return Ok(());
-            }
-            pack => {
-                error!("Unexpected message: {:?} from server when establishing connection, expected Acknowledge message.", pack);
-                return Ok(());
+    let server_capabilities = match ws_rx.next().await {
+        Some(Ok(msg)) => {
+            // servers from before the protocol was versioned acknowledge as version 1
+            match MessagePack::from_str(msg.to_text()?)? {
+                MessagePack::Acknowledge { protocol_version, capabilities } => {
+                    let Some(protocol_version) = negotiate_protocol_version(protocol_version) else {
+                        return Err(format!("Server speaks protocol version {}, which is no longer supported by this client speaking version {}, please upgrade the server", protocol_version, PROTOCOL_VERSION).into());
+                    };
+                    debug!("Speaking protocol version {} with the server, which supports {:?}", protocol_version, &capabilities);
+                    capabilities.into_iter()
+                        .filter(|capability| own_capabilities.contains(capability))
+                        .collect::<Vec<_>>()
+                }
+                MessagePack::Error { message, .. } => {
+                    return Err(format!("Received error message: {}", message).into());
+                }
+                pack => {
+                    return Err(format!("Unexpected message: {:?} from server when establishing connection, expected Acknowledge message.", pack).into());
+                }
             }
         }
+        Some(Err(e)) => return Err(e.into()),
+        None => return Err("Connection closed before the server acknowledged this client".into()),
+    };
+    let push_updates = server_capabilities.contains(&Capability::AddrUpdate);
+    let encoding = Encoding::negotiate(&own_capabilities, &server_capabilities);
+    debug!("Sending messages as {:?}", encoding);
+    info!("connection to server {} established, self id: {}", &args.target_uri, self_id);
+    backoff.reset();
+
+    let mut last_addresses = collect_adapter_addresses()?;
+    let mut last_public_addresses = collect_public_addresses(public_address_monitor, &server_capabilities);
+    if push_updates {
+        ws_tx.send(MessagePack::AddrUpdate {
+            adapter_addresses: last_addresses.clone(),
+            host_info: host_info_for(&server_capabilities),
+            public_addresses: last_public_addresses.clone(),
+        }.to_message_in(encoding)).await?;
     }
-    info!("connection to server {} established, self id: {}", &args.target_uri, &self_id);
-    while let Some(result) = ws_rx.next().await {
-        let message = result?;
-        let text = message.to_text()?;
-        debug!("Received message: {}", text);
-        match MessagePack::from_str(text) {
-            Ok(MessagePack::AddrRequest) => {
-                ws_tx.send(build_ip_addresses_response().to_message()).await
-                    .unwrap_or_else(|e| {
-                        error!("Failed to send message: {}", e)
-                    });
+    let mut watcher = AddressWatcher::new(Duration::from_secs(args.watch_interval));
+    let heartbeat_timeout = Duration::from_secs(args.heartbeat_timeout);
+    let mut heartbeat = tokio::time::interval(Duration::from_secs(args.heartbeat_interval));
+    heartbeat.reset();
+    let mut last_received = Instant::now();
+    loop {
+        tokio::select! {
+            result = ws_rx.next() => {
+                let Some(result) = result else {
+                    break;
+                };
+                last_received = Instant::now();
+                let pack = match result? {
+                    Message::Text(text) => {
+                        debug!("Received message: {}", text);
+                        MessagePack::from_str(&text).map_err(|e| e.to_string())
+                    }
+                    Message::Binary(bytes) => {
+                        let pack = MessagePack::from_binary(&bytes).map_err(|e| e.to_string());
+                        debug!("Received binary message of {} bytes: {:?}", bytes.len(), pack);
+                        pack
+                    }
+                    Message::Close(_) => break,
+                    Message::Ping(_) | Message::Pong(_) | Message::Frame(_) => continue,
+                };
+                match pack {
+                    Ok(MessagePack::AddrRequest { request_id }) => {
+                        let response = match collect_adapter_addresses() {
+                            Ok(addresses) => {
+                                last_addresses = addresses;
+                                last_public_addresses = collect_public_addresses(public_address_monitor, &server_capabilities);
+                                MessagePack::AddrResponse {
+                                    request_id,
+                                    adapter_addresses: last_addresses.clone(),
+                                    host_info: host_info_for(&server_capabilities),
+                                    public_addresses: last_public_addresses.clone(),
+                                }
+                            }
+                            Err(e) => {
+                                error!("Failed to list network interfaces: {}", e);
+                                MessagePack::Error { message: format!("Failed to list network interfaces: {}", e), request_id }
+                            }
+                        };
+                        ws_tx.send(response.to_message_in(encoding)).await
+                            .unwrap_or_else(|e| {
+                                error!("Failed to send message: {}", e)
+                            });
+                    }
+                    Ok(MessagePack::Error { message, .. }) => {
+                        error!("Received error message: {}", message);
+                    }
+                    Err(e) => {
+                        error!("Failed to parse message: {}", e);
+                    }
+                    Ok(pack) => {
+                        error!("Unexpected message: {:?}", pack);
+                    }
+                }
             }
-            Ok(MessagePack::Error { message }) => {
-                error!("Received error message: {}", message);
+            _ = heartbeat.tick() => {
+                if last_received.elapsed() > heartbeat_timeout {
+                    return Err(format!("Server sent nothing for {:?}, considering the connection dead", last_received.elapsed()).into());
+                }
+                ws_tx.send(Message::Ping(Vec::new())).await?;
             }
-            Err(e) => {
-                error!("Failed to parse message: {}", e);
-            }
-            _ => {
-                error!("Unexpected message: {}", text);
+            _ = addresses_changed(&mut watcher, public_address_monitor), if push_updates => {
+                // tried again on the next change or poll
+                let addresses = match collect_adapter_addresses() {
+                    Ok(addresses) => addresses,
+                    Err(e) => {
+                        warn!("Failed to list network interfaces: {}", e);
+                        continue;
+                    }
+                };
+                let public_addresses = collect_public_addresses(public_address_monitor, &server_capabilities);
+                if addresses != last_addresses || public_addresses != last_public_addresses {
+                    info!("Network addresses changed, sending update to server");
+                    ws_tx.send(MessagePack::AddrUpdate {
+                        adapter_addresses: addresses.clone(),
+                        host_info: host_info_for(&server_capabilities),
+                        public_addresses: public_addresses.clone(),
+                    }.to_message_in(encoding)).await?;
+                    last_addresses = addresses;
+                    last_public_addresses = public_addresses;
+                }
             }
         }
     }
 
     Ok(())
 }
-
-fn build_ip_addresses_response() -> MessagePack {
-    let network_interfaces = list_afinet_netifas().expect("Failed to list network interfaces");
-
-    let mut ip_to_name_map: HashMap<String, IpAddresses> = HashMap::with_capacity(network_interfaces.len());
-
-    for (name, ip) in network_interfaces.iter() {
-        match ip_to_name_map.get_mut(name) {
-            Some(addresses) => {
-                addresses.append_address(*ip);
-            }
-            None => {
-                let mut addresses = IpAddresses::empty(name.to_string());
-                addresses.append_address(*ip);
-                ip_to_name_map.insert(name.to_string(), addresses);
-            }
-        }
-    }
-
-    MessagePack::AddrResponse {
-        adapter_addresses: ip_to_name_map.values()
-            .cloned()
-            .collect::<Vec<IpAddresses>>()
-    }
-}
\ No newline at end of file
@@ -0,0 +1,229 @@
+use std::collections::BTreeSet;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::sync::Arc;
+use std::time::Duration;
+
+use rand::Rng;
+use tokio::net::{lookup_host, UdpSocket};
+use tokio::sync::{watch, Notify};
+use tracing::{debug, warn};
+
+const BINDING_REQUEST: u16 = 0x0001;
+const BINDING_SUCCESS_RESPONSE: u16 = 0x0101;
+const MAGIC_COOKIE: u32 = 0x2112_A442;
+const ATTR_MAPPED_ADDRESS: u16 = 0x0001;
+const ATTR_XOR_MAPPED_ADDRESS: u16 = 0x0020;
+const HEADER_LEN: usize = 20;
+
+/// Keeps discovering the public addresses in the background, so that answering the server
+/// never waits for the STUN servers, which may take up to their timeout each.
+pub struct PublicAddressMonitor {
+    addresses: watch::Receiver<Vec<IpAddr>>,
+    refresh: Arc<Notify>,
+}
+
+impl PublicAddressMonitor {
+    pub fn spawn(servers: Vec<String>, timeout: Duration, refresh_interval: Duration) -> PublicAddressMonitor {
+        let (addresses_tx, addresses) = watch::channel(Vec::new());
+        let refresh = Arc::new(Notify::new());
+        let refresh_requested = refresh.clone();
+        tokio::spawn(async move {
+            loop {
+                let discovered = discover_public_addresses(&servers, timeout).await;
+                addresses_tx.send_if_modified(|addresses| {
+                    let changed = *addresses != discovered;
+                    *addresses = discovered;
+                    changed
+                });
+                tokio::select! {
+                    _ = tokio::time::sleep(refresh_interval) => {}
+                    _ = refresh_requested.notified() => {}
+                }
+            }
+        });
+        PublicAddressMonitor { addresses, refresh }
+    }
+
+    /// The addresses discovered last, empty until the first discovery completes
+    pub fn latest(&mut self) -> Vec<IpAddr> {
+        self.addresses.borrow_and_update().clone()
+    }
+
+    /// Discovers the addresses again right away, e.g. because the local addresses changed
+    pub fn refresh(&self) {
+        self.refresh.notify_one();
+    }
+
+    /// Waits until the discovered addresses differ from the ones returned by [`Self::latest`] last
+    pub async fn changed(&mut self) {
+        if self.addresses.changed().await.is_err() {
+            std::future::pending::<()>().await;
+        }
+    }
+}
+
+/// Asks every STUN server, over both IPv4 and IPv6 if it resolves to both, which address
+/// our requests come from, that is the address of this host as seen from the internet.
+pub async fn discover_public_addresses(servers: &[String], timeout: Duration) -> Vec<IpAddr> {
+    let mut result = BTreeSet::new();
+    for server in servers {
+        let server_addresses = match lookup_host(server.as_str()).await {
+            Ok(addresses) => addresses.collect::<Vec<_>>(),
+            Err(e) => {
+                warn!("Failed to resolve STUN server {}: {}", server, e);
+                continue;
+            }
+        };
+        let first_v4 = server_addresses.iter().find(|address| address.is_ipv4());
+        let first_v6 = server_addresses.iter().find(|address| address.is_ipv6());
+        for server_address in first_v4.into_iter().chain(first_v6) {
+            match tokio::time::timeout(timeout, binding_request(*server_address)).await {
+                Ok(Ok(mapped)) => {
+                    debug!("STUN server {} ({}) sees us as {}", server, server_address, mapped);
+                    result.insert(mapped.ip());
+                }
+                Ok(Err(e)) => warn!("STUN request to {} ({}) failed: {}", server, server_address, e),
+                Err(_) => debug!("STUN request to {} ({}) timed out", server, server_address),
+            }
+        }
+    }
+    result.into_iter().collect()
+}
+
+/// Sends a single RFC 5389 Binding request and returns the mapped address of the response
+async fn binding_request(server: SocketAddr) -> io::Result<SocketAddr> {
+    let local: SocketAddr = match server {
+        SocketAddr::V4(_) => (Ipv4Addr::UNSPECIFIED, 0).into(),
+        SocketAddr::V6(_) => (Ipv6Addr::UNSPECIFIED, 0).into(),
+    };
+    let socket = UdpSocket::bind(local).await?;
+    socket.connect(server).await?;
+
+    let transaction_id: [u8; 12] = rand::thread_rng().gen();
+    let mut request = Vec::with_capacity(HEADER_LEN);
+    request.extend_from_slice(&BINDING_REQUEST.to_be_bytes());
+    request.extend_from_slice(&0u16.to_be_bytes());
+    request.extend_from_slice(&MAGIC_COOKIE.to_be_bytes());
+    request.extend_from_slice(&transaction_id);
+
+    let mut buffer = [0u8; 1024];
+    // UDP may drop the datagrams, resend until the caller's timeout gives up
+    let mut resend = tokio::time::interval(Duration::from_millis(500));
+    loop {
+        tokio::select! {
+            _ = resend.tick() => {
+                socket.send(&request).await?;
+            }
+            received = socket.recv(&mut buffer) => {
+                if let Some(mapped) = parse_binding_response(&buffer[..received?], &transaction_id) {
+                    return Ok(mapped);
+                }
+            }
+        }
+    }
+}
+
+fn parse_binding_response(response: &[u8], transaction_id: &[u8; 12]) -> Option<SocketAddr> {
+    if response.len() < HEADER_LEN
+        || u16::from_be_bytes([response[0], response[1]]) != BINDING_SUCCESS_RESPONSE
+        || u32::from_be_bytes(response[4..8].try_into().ok()?) != MAGIC_COOKIE
+        || &response[8..20] != transaction_id {
+        return None;
+    }
+    let length = u16::from_be_bytes([response[2], response[3]]) as usize;
+    let mut attributes = response.get(HEADER_LEN..HEADER_LEN + length)?;
+    let mut mapped_address = None;
+    while attributes.len() >= 4 {
+        let attribute_type = u16::from_be_bytes([attributes[0], attributes[1]]);
+        let attribute_length = u16::from_be_bytes([attributes[2], attributes[3]]) as usize;
+        let value = attributes.get(4..4 + attribute_length)?;
+        match attribute_type {
+            ATTR_XOR_MAPPED_ADDRESS => return parse_address(value, Some(&response[4..20])),
+            ATTR_MAPPED_ADDRESS => mapped_address = parse_address(value, None),
+            _ => {}
+        }
+        // attributes are padded to a multiple of 4 bytes
+        let padded_length = (4 + attribute_length + 3) & !3;
+        attributes = attributes.get(padded_length..).unwrap_or_default();
+    }
+    mapped_address
+}
+
+/// Parses a (XOR-)MAPPED-ADDRESS value, `xor_key` being the magic cookie followed by the transaction id
+fn parse_address(value: &[u8], xor_key: Option<&[u8]>) -> Option<SocketAddr> {
+    let xor = |bytes: &[u8]| -> Vec<u8> {
+        match xor_key {
+            Some(key) => bytes.iter().zip(key).map(|(byte, key)| byte ^ key).collect(),
+            None => bytes.to_vec(),
+        }
+    };
+    let port = xor(value.get(2..4)?);
+    let port = u16::from_be_bytes([port[0], port[1]]);
+    let ip = match value.get(1)? {
+        0x01 => IpAddr::V4(Ipv4Addr::from(<[u8; 4]>::try_from(xor(value.get(4..8)?)).ok()?)),
+        0x02 => IpAddr::V6(Ipv6Addr::from(<[u8; 16]>::try_from(xor(value.get(4..20)?)).ok()?)),
+        _ => return None,
+    };
+    Some(SocketAddr::new(ip, port))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // test vectors of RFC 5769, without the MESSAGE-INTEGRITY and FINGERPRINT attributes
+    const TRANSACTION_ID: [u8; 12] = [0xb7, 0xe7, 0xa7, 0x01, 0xbc, 0x34, 0xd6, 0x86, 0xfa, 0x87, 0xdf, 0xae];
+    const SOFTWARE: [u8; 16] = [0x80, 0x22, 0x00, 0x0b, b't', b'e', b's', b't', b' ', b'v', b'e', b'c', b't', b'o', b'r', 0x20];
+
+    fn binding_response(attributes: &[&[u8]], transaction_id: &[u8; 12]) -> Vec<u8> {
+        let attributes = attributes.concat();
+        let mut response = Vec::new();
+        response.extend_from_slice(&BINDING_SUCCESS_RESPONSE.to_be_bytes());
+        response.extend_from_slice(&(attributes.len() as u16).to_be_bytes());
+        response.extend_from_slice(&MAGIC_COOKIE.to_be_bytes());
+        response.extend_from_slice(transaction_id);
+        response.extend_from_slice(&attributes);
+        response
+    }
+
+    #[test]
+    fn parses_ipv4_xor_mapped_address() {
+        let xor_mapped_address = [0x00, 0x20, 0x00, 0x08, 0x00, 0x01, 0xa1, 0x47, 0xe1, 0x12, 0xa6, 0x43];
+        let response = binding_response(&[&SOFTWARE, &xor_mapped_address], &TRANSACTION_ID);
+        assert_eq!(parse_binding_response(&response, &TRANSACTION_ID), Some("192.0.2.1:32853".parse().unwrap()));
+    }
+
+    #[test]
+    fn parses_ipv6_xor_mapped_address() {
+        let xor_mapped_address = [
+            0x00, 0x20, 0x00, 0x14, 0x00, 0x02, 0xa1, 0x47,
+            0x01, 0x13, 0xa9, 0xfa, 0xa5, 0xd3, 0xf1, 0x79, 0xbc, 0x25, 0xf4, 0xb5, 0xbe, 0xd2, 0xb9, 0xd9,
+        ];
+        let response = binding_response(&[&SOFTWARE, &xor_mapped_address], &TRANSACTION_ID);
+        assert_eq!(
+            parse_binding_response(&response, &TRANSACTION_ID),
+            Some("[2001:db8:1234:5678:11:2233:4455:6677]:32853".parse().unwrap()),
+        );
+    }
+
+    #[test]
+    fn prefers_xor_mapped_address_over_mapped_address() {
+        let mapped_address = [0x00, 0x01, 0x00, 0x08, 0x00, 0x01, 0x80, 0x55, 10, 0, 0, 1];
+        let xor_mapped_address = [0x00, 0x20, 0x00, 0x08, 0x00, 0x01, 0xa1, 0x47, 0xe1, 0x12, 0xa6, 0x43];
+        let response = binding_response(&[&mapped_address, &xor_mapped_address], &TRANSACTION_ID);
+        assert_eq!(parse_binding_response(&response, &TRANSACTION_ID), Some("192.0.2.1:32853".parse().unwrap()));
+        let response = binding_response(&[&mapped_address], &TRANSACTION_ID);
+        assert_eq!(parse_binding_response(&response, &TRANSACTION_ID), Some("10.0.0.1:32853".parse().unwrap()));
+    }
+
+    #[test]
+    fn ignores_responses_to_other_transactions() {
+        let xor_mapped_address = [0x00, 0x20, 0x00, 0x08, 0x00, 0x01, 0xa1, 0x47, 0xe1, 0x12, 0xa6, 0x43];
+        let response = binding_response(&[&xor_mapped_address], &[0; 12]);
+        assert_eq!(parse_binding_response(&response, &TRANSACTION_ID), None);
+        // truncated attribute
+        let response = binding_response(&[&xor_mapped_address[..10]], &TRANSACTION_ID);
+        assert_eq!(parse_binding_response(&response, &TRANSACTION_ID), None);
+    }
+}
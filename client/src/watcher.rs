@@ -0,0 +1,118 @@
+use std::time::Duration;
+
+use tokio::time::{interval, Interval, MissedTickBehavior};
+#[cfg(target_os = "linux")]
+use tracing::warn;
+
+/// Wakes up whenever the network addresses of this host may have changed.
+///
+/// On Linux the kernel notifies address and link changes through a netlink socket,
+/// the periodic poll is kept on every platform as a fallback.
+pub struct AddressWatcher {
+    poll_interval: Interval,
+    #[cfg(target_os = "linux")]
+    netlink: Option<netlink::NetlinkMonitor>,
+}
+
+impl AddressWatcher {
+    pub fn new(poll_interval: Duration) -> AddressWatcher {
+        let mut poll_interval = interval(poll_interval);
+        poll_interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        poll_interval.reset();
+        AddressWatcher {
+            poll_interval,
+            #[cfg(target_os = "linux")]
+            netlink: netlink::NetlinkMonitor::new()
+                .map_err(|e| warn!("Failed to watch network changes through netlink, falling back to polling: {}", e))
+                .ok(),
+        }
+    }
+
+    pub async fn changed(&mut self) {
+        #[cfg(target_os = "linux")]
+        if let Some(monitor) = &self.netlink {
+            let result = tokio::select! {
+                _ = self.poll_interval.tick() => Ok(()),
+                result = monitor.recv() => result,
+            };
+            if let Err(e) = result {
+                warn!("Failed to receive netlink notification, falling back to polling: {}", e);
+                self.netlink = None;
+            }
+            return;
+        }
+        self.poll_interval.tick().await;
+    }
+}
+
+#[cfg(target_os = "linux")]
+mod netlink {
+    use std::io;
+    use std::mem;
+    use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+
+    use tokio::io::unix::AsyncFd;
+
+    pub struct NetlinkMonitor {
+        fd: AsyncFd<OwnedFd>,
+    }
+
+    impl NetlinkMonitor {
+        pub fn new() -> io::Result<NetlinkMonitor> {
+            // SAFETY: plain socket(2) call, the returned descriptor is owned by `OwnedFd` right away
+            let fd = unsafe {
+                libc::socket(libc::AF_NETLINK, libc::SOCK_RAW | libc::SOCK_NONBLOCK | libc::SOCK_CLOEXEC, libc::NETLINK_ROUTE)
+            };
+            if fd < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+
+            // SAFETY: `sockaddr_nl` is a plain C struct for which all-zero is a valid value
+            let mut address: libc::sockaddr_nl = unsafe { mem::zeroed() };
+            address.nl_family = libc::AF_NETLINK as libc::sa_family_t;
+            address.nl_groups = (libc::RTMGRP_LINK | libc::RTMGRP_IPV4_IFADDR | libc::RTMGRP_IPV6_IFADDR) as u32;
+            let result = unsafe {
+                libc::bind(
+                    fd.as_raw_fd(),
+                    &address as *const libc::sockaddr_nl as *const libc::sockaddr,
+                    mem::size_of::<libc::sockaddr_nl>() as libc::socklen_t,
+                )
+            };
+            if result < 0 {
+                return Err(io::Error::last_os_error());
+            }
+
+            Ok(NetlinkMonitor { fd: AsyncFd::new(fd)? })
+        }
+
+        /// Waits for the next batch of notifications, their content is discarded since
+        /// the caller lists the addresses again anyway.
+        pub async fn recv(&self) -> io::Result<()> {
+            let mut buffer = [0u8; 8192];
+            loop {
+                let mut guard = self.fd.readable().await?;
+                let result = guard.try_io(|fd| {
+                    let received = unsafe {
+                        libc::recv(fd.as_raw_fd(), buffer.as_mut_ptr() as *mut libc::c_void, buffer.len(), 0)
+                    };
+                    if received < 0 {
+                        let error = io::Error::last_os_error();
+                        // the receive queue overflowed and notifications were dropped,
+                        // which still means something has changed
+                        if error.raw_os_error() == Some(libc::ENOBUFS) {
+                            return Ok(());
+                        }
+                        Err(error)
+                    } else {
+                        Ok(())
+                    }
+                });
+                match result {
+                    Ok(result) => return result,
+                    Err(_would_block) => continue,
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,264 @@
+use std::ffi::OsString;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use clap::{ArgAction, ArgGroup, ArgMatches, Command, Parser};
+use clap::error::ErrorKind;
+use clap::parser::ValueSource;
+use toml::{Table, Value};
+
+/// Id of the argument holding the path of the configuration file, which every `Args`
+/// parsed by [`parse_args_with_config_file`] has to declare.
+pub const CONFIG_ARG_ID: &str = "config";
+
+/// Parses the command line arguments of `T` together with the TOML configuration file given
+/// by `--config`, with the precedence file < env < CLI.
+///
+/// Besides plain values, any option may be read from a file or from an environment variable
+/// in the configuration file, e.g. `pwd = { file = "/run/secrets/pwd" }` or `pwd = { env = "MY_PWD" }`.
+pub fn parse_args_with_config_file<T: Parser>() -> T {
+    try_parse_args_with_config_file_from(std::env::args_os()).unwrap_or_else(|e| e.exit())
+}
+
+/// Same as [`parse_args_with_config_file`], for the given arguments and without exiting on errors
+pub fn try_parse_args_with_config_file_from<T, I>(args: I) -> Result<T, clap::Error>
+where
+    T: Parser,
+    I: IntoIterator,
+    I::Item: Into<OsString>,
+{
+    let mut command = T::command();
+    let args: Vec<OsString> = args.into_iter().map(Into::into).collect();
+    let provided = without_requirements(command.clone())
+        .ignore_errors(true)
+        .try_get_matches_from(&args)?;
+    let mut merged_args = args;
+    if let Some(config_path) = provided.get_one::<PathBuf>(CONFIG_ARG_ID) {
+        let file_args = config_file_args(&command, &provided, config_path)?;
+        // the options of the file go right after the binary name, so they are validated like any other argument
+        let insert_at = merged_args.len().min(1);
+        merged_args.splice(insert_at..insert_at, file_args);
+    }
+    let mut matches = command.try_get_matches_from_mut(merged_args)?;
+    T::from_arg_matches_mut(&mut matches).map_err(|e| e.format(&mut command))
+}
+
+/// Reads a secret kept in a file, ignoring the trailing line break most editors add
+pub fn read_secret_file(path: impl AsRef<Path>) -> io::Result<String> {
+    let content = std::fs::read_to_string(path)?;
+    Ok(content.trim_end_matches(['\r', '\n']).to_string())
+}
+
+/// Turns the options of the configuration file into command line arguments, leaving out those
+/// overridden by the environment or the command line: either the option itself, or any other
+/// option of an exclusive group it belongs to, as those are alternatives of each other.
+fn config_file_args(command: &Command, provided: &ArgMatches, path: &Path) -> Result<Vec<OsString>, clap::Error> {
+    let content = std::fs::read_to_string(path)
+        .map_err(|e| config_error(path, format!("cannot be read: {}", e)))?;
+    let table: Table = content.parse()
+        .map_err(|e| config_error(path, format!("is not valid TOML: {}", e)))?;
+    let overridden = |id: &str| matches!(
+        provided.try_contains_id(id).ok().and_then(|_| provided.value_source(id)),
+        Some(ValueSource::EnvVariable | ValueSource::CommandLine)
+    );
+    let mut file_args = Vec::new();
+    for (key, value) in table {
+        let id = key.replace('-', "_");
+        let arg = command.get_arguments()
+            .filter(|arg| !matches!(arg.get_id().as_str(), CONFIG_ARG_ID | "help" | "version"))
+            .find(|arg| arg.get_id() == id.as_str() && arg.get_long().is_some())
+            .ok_or_else(|| config_error(path, format!("contains unknown option `{}`", key)))?;
+        let values = config_values(value)
+            .map_err(|e| config_error(path, format!("has an invalid value for `{}`: {}", key, e)))?;
+        let group_overridden = command.get_groups()
+            .filter(|group| !ArgGroup::clone(group).is_multiple() && group.get_args().any(|member| member == arg.get_id()))
+            .any(|group| group.get_args().any(|member| overridden(member.as_str())));
+        if overridden(id.as_str()) || group_overridden {
+            continue;
+        }
+        let long = arg.get_long().unwrap_or_default();
+        match arg.get_action() {
+            ArgAction::SetTrue | ArgAction::SetFalse => {
+                let expected = matches!(arg.get_action(), ArgAction::SetTrue).to_string();
+                match values.as_slice() {
+                    [value] if *value == expected => file_args.push(format!("--{}", long).into()),
+                    [value] if value == "true" || value == "false" => {}
+                    _ => return Err(config_error(path, format!("has an invalid value for `{}`: expected a boolean", key))),
+                }
+            }
+            action if action.takes_values() => {
+                file_args.extend(values.into_iter().map(|value| format!("--{}={}", long, value).into()));
+            }
+            _ => return Err(config_error(path, format!("cannot set `{}`", key))),
+        }
+    }
+    Ok(file_args)
+}
+
+/// Only what was given by the environment or the command line is of interest before merging
+/// the configuration file, which may well provide the required options.
+fn without_requirements(mut command: Command) -> Command {
+    let groups: Vec<String> = command.get_groups().map(|group| group.get_id().to_string()).collect();
+    for group in groups {
+        command = command.mut_group(group, |group| group.required(false));
+    }
+    let args: Vec<String> = command.get_arguments().map(|arg| arg.get_id().to_string()).collect();
+    for arg in args {
+        command = command.mut_arg(arg, |arg| arg.required(false));
+    }
+    command
+}
+
+fn config_values(value: Value) -> Result<Vec<String>, String> {
+    match value {
+        Value::String(value) => Ok(vec![value]),
+        Value::Integer(value) => Ok(vec![value.to_string()]),
+        Value::Float(value) => Ok(vec![value.to_string()]),
+        Value::Boolean(value) => Ok(vec![value.to_string()]),
+        Value::Datetime(value) => Ok(vec![value.to_string()]),
+        Value::Array(values) => {
+            let mut result = Vec::with_capacity(values.len());
+            for value in values {
+                result.extend(config_values(value)?);
+            }
+            Ok(result)
+        }
+        Value::Table(table) => secret_value(&table).map(|value| vec![value]),
+    }
+}
+
+fn secret_value(table: &Table) -> Result<String, String> {
+    match (table.get("file"), table.get("env"), table.len()) {
+        (Some(Value::String(file)), None, 1) => read_secret_file(file)
+            .map_err(|e| format!("cannot read file {}: {}", file, e)),
+        (None, Some(Value::String(env)), 1) => std::env::var(env)
+            .map_err(|e| format!("cannot read environment variable {}: {}", env, e)),
+        _ => Err("expected a value, `{ file = \"...\" }` or `{ env = \"...\" }`".to_string()),
+    }
+}
+
+fn config_error(path: &Path, message: String) -> clap::Error {
+    clap::Error::raw(ErrorKind::InvalidValue, format!("configuration file {} {}\n", path.display(), message))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::{Path, PathBuf};
+    use std::sync::Mutex;
+
+    use clap::{ArgGroup, Parser};
+
+    use super::try_parse_args_with_config_file_from;
+
+    #[derive(Parser, Debug)]
+    #[command(group(ArgGroup::new("password").required(true).args(["pwd", "pwd_hash"])))]
+    struct TestArgs {
+        #[arg(long)]
+        config: Option<PathBuf>,
+        #[arg(long, env = "HOST_EXPOSER_CONFIG_TEST_PWD")]
+        pwd: Option<String>,
+        #[arg(long)]
+        pwd_hash: Option<String>,
+        #[arg(long, env = "HOST_EXPOSER_CONFIG_TEST_PORT", default_value = "3030")]
+        port: u16,
+        #[arg(long, requires = "key")]
+        cert: Option<String>,
+        #[arg(long)]
+        key: Option<String>,
+        #[arg(long)]
+        flag: bool,
+        #[arg(long)]
+        tag: Vec<String>,
+    }
+
+    fn write_config(name: &str, content: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("host-exposer-config-test-{}-{}.toml", name, std::process::id()));
+        std::fs::write(&path, content).unwrap();
+        path
+    }
+
+    fn parse(config: &Path, args: &[&str]) -> Result<TestArgs, clap::Error> {
+        parse_with_env(config, args, &[])
+    }
+
+    /// Tests run in parallel, so the environment may only be touched while holding the lock
+    fn parse_with_env(config: &Path, args: &[&str], env: &[(&str, &str)]) -> Result<TestArgs, clap::Error> {
+        static ENV_LOCK: Mutex<()> = Mutex::new(());
+        let _guard = ENV_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        for (key, value) in env {
+            std::env::set_var(key, value);
+        }
+        let mut argv = vec!["test".to_string(), "--config".to_string(), config.display().to_string()];
+        argv.extend(args.iter().map(|arg| arg.to_string()));
+        let result = try_parse_args_with_config_file_from(argv);
+        for (key, _) in env {
+            std::env::remove_var(key);
+        }
+        result
+    }
+
+    #[test]
+    fn file_values_are_used() {
+        let config = write_config("file", "pwd = \"file\"\nport = 4000\nflag = true\ntag = [\"a\", \"b\"]\n");
+        let args = parse(&config, &[]).unwrap();
+        assert_eq!(args.pwd.as_deref(), Some("file"));
+        assert_eq!(args.port, 4000);
+        assert!(args.flag);
+        assert_eq!(args.tag, ["a", "b"]);
+    }
+
+    #[test]
+    fn cli_overrides_file() {
+        let config = write_config("cli", "pwd = \"file\"\nport = 4000\ntag = [\"a\"]\n");
+        let args = parse(&config, &["--pwd", "cli", "--port", "5000", "--tag", "b"]).unwrap();
+        assert_eq!(args.pwd.as_deref(), Some("cli"));
+        assert_eq!(args.port, 5000);
+        assert_eq!(args.tag, ["b"]);
+    }
+
+    #[test]
+    fn env_overrides_file() {
+        let config = write_config("env", "port = 4000\npwd = \"file\"\n");
+        let args = parse_with_env(&config, &[], &[("HOST_EXPOSER_CONFIG_TEST_PORT", "6000")]).unwrap();
+        assert_eq!(args.port, 6000);
+    }
+
+    #[test]
+    fn group_member_from_cli_drops_file_alternatives() {
+        let config = write_config("group-cli", "pwd_hash = \"file-hash\"\n");
+        let args = parse(&config, &["--pwd", "cli"]).unwrap();
+        assert_eq!(args.pwd.as_deref(), Some("cli"));
+        assert_eq!(args.pwd_hash, None);
+    }
+
+    #[test]
+    fn group_member_from_env_drops_file_alternatives() {
+        let config = write_config("group-env", "pwd_hash = \"file-hash\"\n");
+        let args = parse_with_env(&config, &[], &[("HOST_EXPOSER_CONFIG_TEST_PWD", "env")]).unwrap();
+        assert_eq!(args.pwd.as_deref(), Some("env"));
+        assert_eq!(args.pwd_hash, None);
+    }
+
+    #[test]
+    fn file_satisfies_required_group() {
+        let config = write_config("required", "pwd_hash = \"file-hash\"\n");
+        assert_eq!(parse(&config, &[]).unwrap().pwd_hash.as_deref(), Some("file-hash"));
+        let config = write_config("required-missing", "port = 4000\n");
+        assert!(parse(&config, &[]).is_err());
+    }
+
+    #[test]
+    fn requires_is_checked_for_file_values() {
+        let config = write_config("requires", "pwd = \"file\"\ncert = \"cert.pem\"\n");
+        assert!(parse(&config, &[]).is_err());
+        let args = parse(&config, &["--key", "key.pem"]).unwrap();
+        assert_eq!(args.cert.as_deref(), Some("cert.pem"));
+        assert_eq!(args.key.as_deref(), Some("key.pem"));
+    }
+
+    #[test]
+    fn unknown_options_are_rejected() {
+        let config = write_config("unknown", "pwd = \"file\"\nunknown = 1\n");
+        assert!(parse(&config, &[]).is_err());
+    }
+}
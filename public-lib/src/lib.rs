@@ -1,3 +1,4 @@
 pub mod tracing;
 pub mod message;
 pub mod times;
+pub mod config;
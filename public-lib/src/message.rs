@@ -6,48 +6,276 @@ use serde::{Deserialize, Serialize};
 use tokio_tungstenite::tungstenite::Message;
 use uuid::Uuid;
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
+/// Scope of a single adapter address, as far as it matters for reaching the host.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+#[serde(rename_all = "snake_case")]
+pub enum AddressScope {
+    Global,
+    /// IPv6 temporary (privacy extension) address, globally routable but short-lived
+    Temporary,
+    /// IPv6 unique local address, `fc00::/7`
+    UniqueLocal,
+    /// IPv4 private address, `10.0.0.0/8`, `172.16.0.0/12` or `192.168.0.0/16`
+    Private,
+    LinkLocal,
+    Loopback,
+}
+
+impl AddressScope {
+    pub fn of(ip: &IpAddr, temporary: bool) -> AddressScope {
+        match ip {
+            IpAddr::V4(v4) => {
+                if v4.is_loopback() {
+                    AddressScope::Loopback
+                } else if v4.is_link_local() {
+                    AddressScope::LinkLocal
+                } else if v4.is_private() {
+                    AddressScope::Private
+                } else {
+                    AddressScope::Global
+                }
+            }
+            IpAddr::V6(v6) => {
+                let first_segment = v6.segments()[0];
+                if v6.is_loopback() {
+                    AddressScope::Loopback
+                } else if first_segment & 0xffc0 == 0xfe80 {
+                    AddressScope::LinkLocal
+                } else if first_segment & 0xfe00 == 0xfc00 {
+                    AddressScope::UniqueLocal
+                } else if temporary {
+                    AddressScope::Temporary
+                } else {
+                    AddressScope::Global
+                }
+            }
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct AdapterAddress {
+    pub ip: IpAddr,
+    pub prefix_len: Option<u8>,
+    pub scope: AddressScope,
+}
+
+impl AdapterAddress {
+    pub fn new(ip: IpAddr, prefix_len: Option<u8>, temporary: bool) -> AdapterAddress {
+        AdapterAddress {
+            ip,
+            prefix_len,
+            scope: AddressScope::of(&ip, temporary),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+#[serde(from = "IpAddressesRepr")]
 pub struct IpAddresses {
     pub name: String,
-    pub v4: Option<Ipv4Addr>,
-    pub v6: Option<Ipv6Addr>,
+    /// Hardware address of the adapter, e.g. `00:1a:2b:3c:4d:5e`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mac: Option<String>,
+    pub addresses: Vec<AdapterAddress>,
 }
 
 impl IpAddresses {
     pub fn empty(name: String) -> IpAddresses {
         IpAddresses {
             name,
-            v4: None,
-            v6: None,
+            mac: None,
+            addresses: Vec::new(),
         }
     }
 
-    pub fn append_address(&mut self, ip: IpAddr) -> &mut Self {
-        match ip {
-            IpAddr::V4(v4) => self.v4 = Some(v4),
-            IpAddr::V6(v6) => self.v6 = Some(v6),
+    pub fn append_address(&mut self, address: AdapterAddress) -> &mut Self {
+        if !self.addresses.contains(&address) {
+            self.addresses.push(address);
+            self.addresses.sort();
         }
         self
     }
 }
 
+/// Wire representation of [`IpAddresses`], also accepting the `v4`/`v6` fields sent by older clients
+#[derive(Deserialize)]
+struct IpAddressesRepr {
+    name: String,
+    #[serde(default)]
+    mac: Option<String>,
+    #[serde(default)]
+    addresses: Vec<AdapterAddress>,
+    #[serde(default)]
+    v4: Option<Ipv4Addr>,
+    #[serde(default)]
+    v6: Option<Ipv6Addr>,
+}
+
+impl From<IpAddressesRepr> for IpAddresses {
+    fn from(repr: IpAddressesRepr) -> Self {
+        let mut result = IpAddresses::empty(repr.name);
+        result.mac = repr.mac;
+        let legacy_addresses = repr.v4.map(IpAddr::V4).into_iter()
+            .chain(repr.v6.map(IpAddr::V6))
+            .map(|ip| AdapterAddress::new(ip, None, false));
+        for address in repr.addresses.into_iter().chain(legacy_addresses) {
+            result.append_address(address);
+        }
+        result
+    }
+}
+
+/// Description of the machine running the client, reported along with its addresses
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct HostInfo {
+    pub hostname: Option<String>,
+    /// Name and version of the operating system, e.g. `Linux (Debian 12)`
+    pub os_version: Option<String>,
+    pub kernel_version: Option<String>,
+    pub arch: String,
+    /// Seconds since the machine booted
+    pub uptime: u64,
+    pub client_version: String,
+}
+
+/// Version of the protocol spoken by this build, raised on every incompatible change of the messages
+pub const PROTOCOL_VERSION: u32 = 2;
+/// Oldest version of the protocol this build can still speak
+pub const MIN_PROTOCOL_VERSION: u32 = 1;
+
+/// Clients from before the protocol was versioned do not send any version
+fn unversioned_protocol_version() -> u32 {
+    1
+}
+
+/// The version both sides speak, the older of the two, if this build still speaks it
+pub fn negotiate_protocol_version(peer_version: u32) -> Option<u32> {
+    let version = peer_version.min(PROTOCOL_VERSION);
+    (version >= MIN_PROTOCOL_VERSION).then_some(version)
+}
+
+/// Optional features of the protocol, each side advertises the ones it supports in the handshake
+/// and only uses those the other side advertised as well
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum Capability {
+    /// Addresses pushed by the client whenever they change, through `AddrUpdate`
+    AddrUpdate,
+    /// `host_info` in the address reports
+    HostInfo,
+    /// `public_addresses` in the address reports
+    PublicAddresses,
+    /// Messages after the handshake sent as MessagePack in binary frames, see [`Encoding`]
+    MessagePackEncoding,
+    /// Advertised by a newer peer, meaningless to this build
+    #[serde(other)]
+    Unknown,
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub enum MessagePack {
     Establish {
         id: Uuid,
+        #[serde(default = "unversioned_protocol_version")]
+        protocol_version: u32,
+        #[serde(default)]
+        capabilities: Vec<Capability>,
+        /// Base64 of the shared server password, left empty by clients using a token
+        #[serde(default)]
         password: String,
+        /// Enrollment token issued for this client
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        token: Option<String>,
+        /// Name the client wants to be listed under
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        name: Option<String>,
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        tags: Vec<String>,
+    },
+    /// Sent as the bare string `"Acknowledge"` to protocol version 1 clients
+    Acknowledge {
+        /// The version the server speaks, the connection uses the older of both
+        protocol_version: u32,
+        capabilities: Vec<Capability>,
+    },
+    /// Sent by the server, `request_id` is echoed in the response so that several requests may be in flight.
+    /// Protocol version 1 has no request ids, the request is the bare string `"AddrRequest"` and the
+    /// responses come in the order of the requests.
+    AddrRequest {
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        request_id: Option<u64>,
     },
-    Acknowledge,
-    AddrRequest,
     AddrResponse {
-        adapter_addresses: Vec<IpAddresses>
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        request_id: Option<u64>,
+        adapter_addresses: Vec<IpAddresses>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        host_info: Option<HostInfo>,
+        /// Addresses of the client as seen from the internet, discovered through STUN
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        public_addresses: Vec<IpAddr>,
+    },
+    /// Pushed by the client without a request whenever its set of addresses changes
+    AddrUpdate {
+        adapter_addresses: Vec<IpAddresses>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        host_info: Option<HostInfo>,
+        /// Addresses of the client as seen from the internet, discovered through STUN
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        public_addresses: Vec<IpAddr>,
     },
     Error {
-        message: String
+        message: String,
+        /// The request which failed, if the error answers one
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        request_id: Option<u64>,
     },
 }
 
+/// How the messages are put in websocket frames, the handshake is always JSON
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Encoding {
+    /// JSON in text frames, readable when debugging
+    #[default]
+    Json,
+    /// MessagePack in binary frames, a fraction of the size of JSON
+    MessagePack,
+}
+
+impl Encoding {
+    /// MessagePack is only used if both sides advertised it
+    pub fn negotiate(own_capabilities: &[Capability], peer_capabilities: &[Capability]) -> Encoding {
+        if own_capabilities.contains(&Capability::MessagePackEncoding) && peer_capabilities.contains(&Capability::MessagePackEncoding) {
+            Encoding::MessagePack
+        } else {
+            Encoding::Json
+        }
+    }
+}
+
+/// The messages without any field in protocol version 1, sent as bare strings
+const LEGACY_ACKNOWLEDGE: &str = "\"Acknowledge\"";
+const LEGACY_ADDR_REQUEST: &str = "\"AddrRequest\"";
+
 impl MessagePack {
+    /// The JSON of the message in the given version of the protocol
+    pub fn to_string_for(&self, protocol_version: u32) -> String {
+        match self {
+            MessagePack::Acknowledge { .. } if protocol_version < 2 => LEGACY_ACKNOWLEDGE.to_string(),
+            MessagePack::AddrRequest { .. } if protocol_version < 2 => LEGACY_ADDR_REQUEST.to_string(),
+            _ => self.to_string(),
+        }
+    }
+
+    /// Protocol version 1 peers never negotiate MessagePack, so they always get JSON
+    pub fn to_framework_message_for(&self, protocol_version: u32, encoding: Encoding) -> axum::extract::ws::Message {
+        match encoding {
+            Encoding::Json => axum::extract::ws::Message::Text(self.to_string_for(protocol_version)),
+            Encoding::MessagePack => self.to_framework_message_in(encoding),
+        }
+    }
+
     pub fn to_message(&self) -> Message {
         Message::Text(self.to_string())
     }
@@ -55,6 +283,30 @@ impl MessagePack {
     pub fn to_framework_message(&self) -> axum::extract::ws::Message {
         axum::extract::ws::Message::Text(self.to_string())
     }
+
+    pub fn to_message_in(&self, encoding: Encoding) -> Message {
+        match encoding {
+            Encoding::Json => self.to_message(),
+            Encoding::MessagePack => Message::Binary(self.to_binary()),
+        }
+    }
+
+    pub fn to_framework_message_in(&self, encoding: Encoding) -> axum::extract::ws::Message {
+        match encoding {
+            Encoding::Json => self.to_framework_message(),
+            Encoding::MessagePack => axum::extract::ws::Message::Binary(self.to_binary()),
+        }
+    }
+
+    /// Structs are encoded as maps rather than arrays, so that fields left out when empty
+    /// and fields unknown to an older peer do not shift the others
+    fn to_binary(&self) -> Vec<u8> {
+        rmp_serde::to_vec_named(self).unwrap()
+    }
+
+    pub fn from_binary(bytes: &[u8]) -> Result<MessagePack, rmp_serde::decode::Error> {
+        rmp_serde::from_slice(bytes)
+    }
 }
 
 impl Display for MessagePack {
@@ -67,7 +319,85 @@ impl FromStr for MessagePack {
     type Err = serde_json::Error;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        serde_json::from_str(s)
+        match s.trim() {
+            LEGACY_ACKNOWLEDGE => Ok(MessagePack::Acknowledge { protocol_version: 1, capabilities: Vec::new() }),
+            LEGACY_ADDR_REQUEST => Ok(MessagePack::AddrRequest { request_id: None }),
+            s => serde_json::from_str(s),
+        }
     }
 }
 
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn legacy_addresses_are_read_as_adapter_addresses() {
+        let json = r#"{"AddrResponse":{"adapter_addresses":[{"name":"eth0","v4":"192.168.1.2","v6":"2001:db8::1"},{"name":"lo","v4":"127.0.0.1","v6":null}]}}"#;
+        let MessagePack::AddrResponse { request_id, adapter_addresses, host_info, public_addresses } = MessagePack::from_str(json).unwrap() else {
+            panic!("not an AddrResponse");
+        };
+        assert_eq!(request_id, None);
+        assert_eq!(host_info, None);
+        assert!(public_addresses.is_empty());
+        assert_eq!(adapter_addresses, vec![
+            IpAddresses {
+                name: "eth0".to_string(),
+                mac: None,
+                addresses: vec![
+                    AdapterAddress::new("192.168.1.2".parse().unwrap(), None, false),
+                    AdapterAddress::new("2001:db8::1".parse().unwrap(), None, false),
+                ],
+            },
+            IpAddresses {
+                name: "lo".to_string(),
+                mac: None,
+                addresses: vec![AdapterAddress::new("127.0.0.1".parse().unwrap(), None, false)],
+            },
+        ]);
+        assert_eq!(adapter_addresses[0].addresses[0].scope, AddressScope::Private);
+        assert_eq!(adapter_addresses[0].addresses[1].scope, AddressScope::Global);
+    }
+
+    #[test]
+    fn legacy_addresses_survive_a_round_trip() {
+        let json = r#"{"name":"eth0","v4":"10.0.0.1","v6":"fd00::1"}"#;
+        let addresses: IpAddresses = serde_json::from_str(json).unwrap();
+        let round_tripped: IpAddresses = serde_json::from_str(&serde_json::to_string(&addresses).unwrap()).unwrap();
+        assert_eq!(round_tripped, addresses);
+        let round_tripped: IpAddresses = rmp_serde::from_slice(&rmp_serde::to_vec_named(&addresses).unwrap()).unwrap();
+        assert_eq!(round_tripped, addresses);
+    }
+
+    #[test]
+    fn version_1_messages_are_bare_strings() {
+        let acknowledge = MessagePack::Acknowledge { protocol_version: PROTOCOL_VERSION, capabilities: vec![Capability::AddrUpdate] };
+        assert_eq!(acknowledge.to_string_for(1), r#""Acknowledge""#);
+        let request = MessagePack::AddrRequest { request_id: Some(7) };
+        assert_eq!(request.to_string_for(1), r#""AddrRequest""#);
+
+        assert!(matches!(
+            MessagePack::from_str(&acknowledge.to_string_for(1)).unwrap(),
+            MessagePack::Acknowledge { protocol_version: 1, capabilities } if capabilities.is_empty()
+        ));
+        assert!(matches!(
+            MessagePack::from_str(&request.to_string_for(1)).unwrap(),
+            MessagePack::AddrRequest { request_id: None }
+        ));
+    }
+
+    #[test]
+    fn version_2_messages_keep_their_fields() {
+        let request = MessagePack::AddrRequest { request_id: Some(7) };
+        assert!(matches!(
+            MessagePack::from_str(&request.to_string_for(2)).unwrap(),
+            MessagePack::AddrRequest { request_id: Some(7) }
+        ));
+        let acknowledge = MessagePack::Acknowledge { protocol_version: 2, capabilities: vec![Capability::HostInfo] };
+        assert!(matches!(
+            MessagePack::from_str(&acknowledge.to_string_for(2)).unwrap(),
+            MessagePack::Acknowledge { protocol_version: 2, capabilities } if capabilities == vec![Capability::HostInfo]
+        ));
+    }
+}
@@ -1,12 +1,29 @@
 export interface ClientInformation {
     adapter_addresses: AdapterAddress[]
     entity: Entity
+    online: boolean
+    protocol_version?: number
+    capabilities?: string[]
+    reported_at?: string
+    tags: string[]
 }
 
 export interface AdapterAddress {
     name: string
-    v4?: string
-    v6?: string
+    mac?: string
+    addresses: Address[]
+}
+
+export interface Address {
+    ip: string
+    prefix_len?: number
+    scope:
+        | 'global'
+        | 'temporary'
+        | 'unique_local'
+        | 'private'
+        | 'link_local'
+        | 'loopback'
 }
 
 export interface Entity {
@@ -14,6 +31,20 @@ export interface Entity {
     id: string
     last_fetch_time: string
     name: string
+    hostname?: string
+    os_version?: string
+    kernel_version?: string
+    arch?: string
+    client_version?: string
+    boot_time?: string
+    peer_address?: string
+    public_addresses?: string[]
+    last_seen_time?: string
+    archived: boolean
+    group?: string
+    notes?: string
+    name_set_by_admin: boolean
+    tags_set_by_admin: boolean
 }
 
 export async function testPasswordAuthenticatable(): Promise<boolean> {
@@ -36,11 +67,123 @@ export async function editClientName(targetClientId: string, newName: string) {
     })
 }
 
-export async function getAllClientsInformation(): Promise<ClientInformation[]> {
-    const resp = await fetch('/api/client', publicRequestConfig())
+export async function editClientTags(
+    targetClientId: string,
+    tags: string[]
+): Promise<string[]> {
+    const resp = await fetch(`/api/client/${targetClientId}/tags`, {
+        method: 'put',
+        body: JSON.stringify({ tags }),
+        ...publicRequestConfig()
+    })
+    return (await resp.json()) as string[]
+}
+
+export async function editClientGroup(
+    targetClientId: string,
+    group: string
+) {
+    await fetch(`/api/client/${targetClientId}/group`, {
+        method: 'put',
+        body: JSON.stringify({ group }),
+        ...publicRequestConfig()
+    })
+}
+
+export async function editClientNotes(
+    targetClientId: string,
+    notes: string
+) {
+    await fetch(`/api/client/${targetClientId}/notes`, {
+        method: 'put',
+        body: JSON.stringify({ notes }),
+        ...publicRequestConfig()
+    })
+}
+
+export async function setClientArchived(
+    targetClientId: string,
+    archived: boolean
+) {
+    await fetch(`/api/client/${targetClientId}/archive`, {
+        method: archived ? 'put' : 'delete',
+        ...publicRequestConfig()
+    })
+}
+
+export async function deleteClient(targetClientId: string) {
+    await fetch(`/api/client/${targetClientId}?disconnect=true`, {
+        method: 'delete',
+        ...publicRequestConfig()
+    })
+}
+
+export async function getAllClientsInformation(
+    refresh: boolean = false,
+    archived: boolean = false,
+    tag: string | null = ''
+): Promise<ClientInformation[]> {
+    const params = new URLSearchParams({
+        refresh: `${refresh}`,
+        archived: `${archived}`
+    })
+    if (tag?.trim()) {
+        params.set('tag', tag.trim())
+    }
+    const resp = await fetch(`/api/client?${params}`, publicRequestConfig())
     return (await resp.json()) as ClientInformation[]
 }
 
+export interface ClientEvent {
+    event: 'connected' | 'disconnected' | 'address_changed' | 'renamed' | 'lagged'
+    client_id?: string
+    time?: string
+}
+
+/**
+ * Subscribes to `/api/events`, EventSource cannot send the Authorization header,
+ * so the stream is read through fetch instead. Resolves once the stream ends.
+ */
+export async function subscribeClientEvents(
+    onEvent: (event: ClientEvent) => void,
+    signal: AbortSignal
+) {
+    const resp = await fetch('/api/events', {
+        ...publicRequestConfig(),
+        signal
+    })
+    const reader = resp.body!.pipeThrough(new TextDecoderStream()).getReader()
+    let buffer = ''
+    while (true) {
+        const { value, done } = await reader.read()
+        if (done) {
+            return
+        }
+        buffer += value
+        const messages = buffer.split('\n\n')
+        buffer = messages.pop()!
+        for (const message of messages) {
+            const fields = new Map<string, string>()
+            for (const line of message.split('\n')) {
+                const separator = line.indexOf(':')
+                if (separator > 0) {
+                    fields.set(
+                        line.slice(0, separator),
+                        line.slice(separator + 1).trim()
+                    )
+                }
+            }
+            const event = fields.get('event')
+            if (event) {
+                onEvent({
+                    ...JSON.parse(fields.get('data') ?? '{}'),
+                    event
+                } as ClientEvent)
+            }
+        }
+    }
+}
+
 function publicRequestConfig(): RequestInit {
     const password = sessionStorage.getItem("password")!!
     return {
@@ -1,11 +1,28 @@
-use axum::extract::{Request, State};
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use argon2::{Argon2, PasswordHash, PasswordHasher, PasswordVerifier as _};
+use argon2::password_hash::rand_core::OsRng;
+use argon2::password_hash::SaltString;
+use axum::extract::{ConnectInfo, Request, State};
 use axum::http;
 use axum::http::StatusCode;
 use axum::middleware::Next;
-use axum::response::Response;
-use rand::Rng;
+use axum::response::{IntoResponse, Response};
+use base64::Engine;
+use base64::prelude::{BASE64_STANDARD, BASE64_URL_SAFE_NO_PAD};
+use hmac::{Hmac, Mac};
+use rand::{Rng, RngCore};
+use sha2::{Digest, Sha256};
+use subtle::ConstantTimeEq;
+use tokio::time::Instant;
+use tracing::{debug, warn};
 
 use crate::AppState;
+use crate::peer::resolve_peer_address;
+use crate::result::HEError;
 
 pub fn random_password(length: u8) -> String {
     const RANDOM_CHARSET: &[u8] = b"abcdefghijklmnopqrstuvwxyzABCDEFGHIJKLMNPQRSTUVWXYZ123456789!(),._-?@#[]`~=+*^%";
@@ -17,13 +34,234 @@ pub fn random_password(length: u8) -> String {
     password
 }
 
-pub async fn basic_auth(State(state): State<AppState>, req: Request, next: Next) -> Result<Response, StatusCode> {
+/// Generates a new client enrollment token, only its hash is ever stored
+pub fn random_token() -> String {
+    let mut bytes = [0u8; 32];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    BASE64_URL_SAFE_NO_PAD.encode(bytes)
+}
+
+pub fn hash_token(token: &str) -> String {
+    format!("{:x}", Sha256::digest(token.as_bytes()))
+}
+
+pub fn hash_password(password: &str) -> Result<String, argon2::password_hash::Error> {
+    let salt = SaltString::generate(&mut OsRng);
+    Ok(Argon2::default().hash_password(password.as_bytes(), &salt)?.to_string())
+}
+
+/// Verifies passwords against the Argon2 hash of the server password.
+///
+/// Argon2 is deliberately slow, so the HMAC of the last verified password is kept and compared
+/// in constant time to let the dashboard's repeated requests through cheaply. The HMAC key is
+/// random and never leaves the process, so the cached value cannot be brute-forced offline.
+pub struct PasswordVerifier {
+    password_hash: String,
+    cache_key: [u8; 32],
+    verified_mac: Mutex<Option<[u8; 32]>>,
+}
+
+impl PasswordVerifier {
+    pub fn new(password_hash: String) -> Result<PasswordVerifier, argon2::password_hash::Error> {
+        PasswordHash::new(&password_hash)?;
+        let mut cache_key = [0u8; 32];
+        OsRng.fill_bytes(&mut cache_key);
+        Ok(PasswordVerifier {
+            password_hash,
+            cache_key,
+            verified_mac: Mutex::new(None),
+        })
+    }
+
+    pub async fn verify(&self, password: Vec<u8>) -> bool {
+        let mut mac = Hmac::<Sha256>::new_from_slice(&self.cache_key).expect("HMAC accepts keys of any length");
+        mac.update(&password);
+        let password_mac: [u8; 32] = mac.finalize().into_bytes().into();
+        if let Some(verified_mac) = *self.verified_mac.lock().unwrap() {
+            if bool::from(verified_mac.ct_eq(&password_mac)) {
+                return true;
+            }
+        }
+        let password_hash = self.password_hash.clone();
+        let verified = tokio::task::spawn_blocking(move || {
+            PasswordHash::new(&password_hash)
+                .is_ok_and(|hash| Argon2::default().verify_password(&password, &hash).is_ok())
+        }).await.unwrap_or(false);
+        if verified {
+            *self.verified_mac.lock().unwrap() = Some(password_mac);
+        }
+        verified
+    }
+
+    /// Verifies a base64 encoded password, as sent by the web UI and the clients
+    pub async fn verify_base64(&self, base64_password: &str) -> bool {
+        match BASE64_STANDARD.decode(base64_password) {
+            Ok(password) => self.verify(password).await,
+            Err(_) => false,
+        }
+    }
+}
+
+/// Failed authentications of an address within the current window
+struct AuthFailures {
+    count: u32,
+    window_start: Instant,
+    banned_until: Option<Instant>,
+}
+
+/// Bans the addresses failing to authenticate too often for a while, both on the REST API
+/// and on the client websocket, so that the passwords and tokens cannot be guessed at speed.
+/// Successful authentications do not reset the failures, which only expire with their window,
+/// as holding one valid credential must not allow guessing the others.
+pub struct AuthFailureLimiter {
+    max_failures: u32,
+    window: Duration,
+    ban_duration: Duration,
+    failures: Mutex<HashMap<IpAddr, AuthFailures>>,
+}
+
+impl AuthFailureLimiter {
+    /// A `max_failures` of 0 never bans anyone
+    pub fn new(max_failures: u32, window: Duration, ban_duration: Duration) -> AuthFailureLimiter {
+        AuthFailureLimiter {
+            max_failures,
+            window,
+            ban_duration,
+            failures: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Refuses banned addresses, telling how long the ban lasts
+    pub fn check(&self, address: &IpAddr) -> Result<(), HEError> {
+        let failures = self.failures.lock().unwrap_or_else(|e| e.into_inner());
+        match failures.get(address).and_then(|failures| failures.banned_until) {
+            Some(banned_until) if banned_until > Instant::now() => {
+                let remaining = banned_until - Instant::now();
+                debug!("Refused banned address {}, banned for another {:?}", address, remaining);
+                Err(HEError::TooManyRequests(format!(
+                    "Too many failed authentications, try again in {} seconds", remaining.as_secs() + 1,
+                )))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    pub fn record_failure(&self, address: &IpAddr) {
+        if self.max_failures == 0 {
+            return;
+        }
+        let now = Instant::now();
+        let mut failures = self.failures.lock().unwrap_or_else(|e| e.into_inner());
+        // forget the addresses which did not fail lately, so that the map does not grow forever
+        failures.retain(|_, failures| {
+            failures.banned_until.is_some_and(|banned_until| banned_until > now) || now - failures.window_start < self.window
+        });
+        let entry = failures.entry(*address).or_insert(AuthFailures {
+            count: 0,
+            window_start: now,
+            banned_until: None,
+        });
+        if now - entry.window_start >= self.window {
+            entry.count = 0;
+            entry.window_start = now;
+        }
+        entry.count += 1;
+        if entry.count >= self.max_failures {
+            warn!("Banning {} for {:?} after {} failed authentications within {:?}", address, self.ban_duration, entry.count, self.window);
+            entry.count = 0;
+            entry.window_start = now;
+            entry.banned_until = Some(now + self.ban_duration);
+        }
+    }
+
+    /// Number of addresses currently banned
+    pub fn banned_count(&self) -> usize {
+        let now = Instant::now();
+        self.failures.lock().unwrap_or_else(|e| e.into_inner()).values()
+            .filter(|failures| failures.banned_until.is_some_and(|banned_until| banned_until > now))
+            .count()
+    }
+}
+
+pub async fn basic_auth(State(state): State<AppState>, req: Request, next: Next) -> Result<Response, Response> {
+    let peer_address = req.extensions().get::<ConnectInfo<SocketAddr>>()
+        .map(|ConnectInfo(peer)| resolve_peer_address(*peer, req.headers(), &state.trusted_proxies));
+    if let Some(peer_address) = &peer_address {
+        state.auth_limiter.check(peer_address).map_err(IntoResponse::into_response)?;
+    }
     if let Some(authorization) = req.headers().get(http::header::AUTHORIZATION) {
-        if let Ok(auth_token) = authorization.to_str() {
-            if auth_token == format!("Basic {}", state.server_base64_password) {
+        if let Some(base64_password) = authorization.to_str().ok().and_then(|value| value.strip_prefix("Basic ")) {
+            if state.password_verifier.verify_base64(base64_password).await {
                 return Ok(next.run(req).await);
             }
+            // only a wrong password counts, not a request missing the header altogether
+            if let Some(peer_address) = &peer_address {
+                warn!("Failed REST API authentication from {}", peer_address);
+                state.auth_limiter.record_failure(peer_address);
+            }
         }
     }
-    Err(StatusCode::UNAUTHORIZED)
-}
\ No newline at end of file
+    Err(StatusCode::UNAUTHORIZED.into_response())
+}
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv4Addr;
+
+    use tokio::time::advance;
+
+    use super::*;
+
+    const ADDRESS: IpAddr = IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1));
+    const OTHER_ADDRESS: IpAddr = IpAddr::V4(Ipv4Addr::new(192, 0, 2, 2));
+
+    fn limiter() -> AuthFailureLimiter {
+        AuthFailureLimiter::new(3, Duration::from_secs(60), Duration::from_secs(300))
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn bans_after_max_failures_within_the_window() {
+        let limiter = limiter();
+        limiter.record_failure(&ADDRESS);
+        limiter.record_failure(&ADDRESS);
+        assert!(limiter.check(&ADDRESS).is_ok());
+        limiter.record_failure(&ADDRESS);
+        assert!(matches!(limiter.check(&ADDRESS), Err(HEError::TooManyRequests(_))));
+        assert!(limiter.check(&OTHER_ADDRESS).is_ok());
+        assert_eq!(limiter.banned_count(), 1);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn ban_expires_after_its_duration() {
+        let limiter = limiter();
+        for _ in 0..3 {
+            limiter.record_failure(&ADDRESS);
+        }
+        advance(Duration::from_secs(299)).await;
+        assert!(limiter.check(&ADDRESS).is_err());
+        advance(Duration::from_secs(1)).await;
+        assert!(limiter.check(&ADDRESS).is_ok());
+        assert_eq!(limiter.banned_count(), 0);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn failures_expire_with_their_window() {
+        let limiter = limiter();
+        limiter.record_failure(&ADDRESS);
+        limiter.record_failure(&ADDRESS);
+        advance(Duration::from_secs(60)).await;
+        limiter.record_failure(&ADDRESS);
+        limiter.record_failure(&ADDRESS);
+        assert!(limiter.check(&ADDRESS).is_ok());
+        limiter.record_failure(&ADDRESS);
+        assert!(limiter.check(&ADDRESS).is_err());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn zero_max_failures_never_bans() {
+        let limiter = AuthFailureLimiter::new(0, Duration::from_secs(60), Duration::from_secs(300));
+        for _ in 0..10 {
+            limiter.record_failure(&ADDRESS);
+        }
+        assert!(limiter.check(&ADDRESS).is_ok());
+    }
+}
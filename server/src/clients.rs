@@ -1,107 +1,396 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
+use std::net::{IpAddr, SocketAddr};
 use std::str::FromStr;
-use std::sync::Arc;
+use std::sync::{Arc, Mutex as StdMutex};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
 
-use axum::extract::{Path, State, WebSocketUpgrade};
-use axum::extract::ws::{Message, WebSocket};
+use axum::extract::{ConnectInfo, Path, Query, State, WebSocketUpgrade};
+use axum::extract::ws::{CloseFrame, Message, WebSocket};
+use axum::http::HeaderMap;
 use axum::Json;
 use axum::response::IntoResponse;
-use futures_util::{SinkExt, StreamExt};
-use sea_orm::{ColumnTrait, DatabaseConnection, EntityTrait, QueryFilter};
+use futures_util::{future, SinkExt, StreamExt};
+use futures_util::stream::{SplitSink, SplitStream};
+use sea_orm::EntityTrait;
 use serde::{Deserialize, Serialize};
 use serde_json::{json, Value};
-use time::UtcOffset;
-use tokio::sync::{mpsc, RwLock};
+use time::{OffsetDateTime, UtcOffset};
+use tokio::sync::{mpsc, oneshot, RwLock, watch};
+use tokio::time::Instant;
 use tokio_stream::wrappers::UnboundedReceiverStream;
-use tracing::{debug, error, info};
+use tracing::{debug, error, info, warn};
 use uuid::Uuid;
 
-use public_lib::message::{IpAddresses, MessagePack};
+use public_lib::message::{Capability, Encoding, HostInfo, IpAddresses, MessagePack, negotiate_protocol_version, PROTOCOL_VERSION};
 use public_lib::times::local_offset_date_time;
 
-use crate::{AppState, db};
+use crate::{AppState, db, dns};
 use crate::db::client::save_new_client_information;
-use crate::entity::client;
 use crate::entity::prelude::DbClient;
+use crate::events::ClientEvent;
+use crate::peer::resolve_peer_address;
 use crate::result::HEError;
 
-#[derive(Serialize)]
+/// Advertised to every client in the handshake
+const SERVER_CAPABILITIES: [Capability; 4] = [
+    Capability::AddrUpdate,
+    Capability::HostInfo,
+    Capability::PublicAddresses,
+    Capability::MessagePackEncoding,
+];
+
+/// The latest addresses a client reported, either pushed by itself or requested by the server
+#[derive(Serialize, Clone)]
+pub struct AddressSnapshot {
+    pub adapter_addresses: Vec<IpAddresses>,
+    pub reported_at: OffsetDateTime,
+}
+
+impl AddressSnapshot {
+    fn new(adapter_addresses: Vec<IpAddresses>, default_offset: &UtcOffset) -> AddressSnapshot {
+        AddressSnapshot {
+            adapter_addresses,
+            reported_at: local_offset_date_time(default_offset),
+        }
+    }
+}
+
+/// The requests sent to a client and not answered yet, by request id
+#[derive(Clone)]
+struct PendingRequests {
+    next_id: Arc<AtomicU64>,
+    senders: Arc<StdMutex<BTreeMap<u64, oneshot::Sender<MessagePack>>>>,
+    /// Protocol version 1 clients answer without request ids, in the order of the requests
+    in_order: bool,
+}
+
+impl PendingRequests {
+    fn new(in_order: bool) -> Self {
+        PendingRequests { next_id: Arc::default(), senders: Arc::default(), in_order }
+    }
+
+    fn register(&self) -> PendingRequest {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let (tx, rx) = oneshot::channel();
+        self.senders.lock().unwrap_or_else(|e| e.into_inner()).insert(id, tx);
+        PendingRequest { id, rx, requests: self.clone() }
+    }
+
+    /// Hands the response to the request it answers, gives it back if nothing is waiting for it.
+    /// Responses without a request id, from protocol version 1 clients, answer the oldest request,
+    /// which may have been given up on already.
+    fn resolve(&self, request_id: Option<u64>, pack: MessagePack) -> Option<MessagePack> {
+        let mut senders = self.senders.lock().unwrap_or_else(|e| e.into_inner());
+        let sender = match request_id {
+            Some(request_id) => senders.remove(&request_id),
+            None => senders.pop_first().map(|(_, sender)| sender),
+        };
+        drop(senders);
+        match sender {
+            Some(sender) => sender.send(pack).err(),
+            None => Some(pack),
+        }
+    }
+
+    /// Fails every request still waiting, as the connection they were sent through is gone
+    fn clear(&self) {
+        self.senders.lock().unwrap_or_else(|e| e.into_inner()).clear();
+    }
+}
+
+/// Removes itself from the pending requests when dropped, so that requests given up on,
+/// e.g. by a timeout, do not pile up. Requests answered in order are kept until their late
+/// response arrives, which must not be taken for the answer of the next request.
+struct PendingRequest {
+    id: u64,
+    rx: oneshot::Receiver<MessagePack>,
+    requests: PendingRequests,
+}
+
+impl Drop for PendingRequest {
+    fn drop(&mut self) {
+        if self.requests.in_order {
+            return;
+        }
+        self.requests.senders.lock().unwrap_or_else(|e| e.into_inner()).remove(&self.id);
+    }
+}
+
+#[derive(Serialize, Clone)]
 pub struct Client {
     #[serde(default)]
     id: Uuid,
+    /// Version of the protocol negotiated with the client
+    protocol_version: u32,
+    /// Capabilities advertised by the client which this server supports as well
+    capabilities: Vec<Capability>,
+    /// Encoding of the messages sent to the client after the handshake
+    #[serde(skip)]
+    encoding: Encoding,
     #[serde(skip)]
     handler_tx: mpsc::UnboundedSender<Message>,
     #[serde(skip)]
-    client_rx: UnboundedReceiverStream<Message>,
+    pending_requests: PendingRequests,
+    #[serde(skip)]
+    latest_addresses: Arc<RwLock<Option<AddressSnapshot>>>,
+    /// Authenticated with the server password, so it cannot be locked out by revoking tokens
+    #[serde(skip)]
+    password_client: bool,
+    #[serde(skip)]
+    session_rx: watch::Receiver<()>,
+    /// Whether the addresses requested through the REST API may still be stored, which is
+    /// done outside the session, turned off before deleting the client
+    #[serde(skip)]
+    recording: Arc<RwLock<bool>>,
 }
 
 impl Client {
-    async fn get_adapter_addresses(&mut self, db: &DatabaseConnection, default_offset: &UtcOffset) -> Result<Vec<IpAddresses>, HEError> {
-        self.handler_tx.send(MessagePack::AddrRequest.to_framework_message())?;
-        if let Some(message) = self.client_rx.next().await {
-            match MessagePack::from_str(message.to_text().unwrap()) {
-                Ok(MessagePack::AddrResponse { adapter_addresses }) => {
-                    save_new_client_information(&self.id, db, default_offset).await?;
-                    Ok(adapter_addresses)
-                }
-                Ok(_) => {
-                    Err(HEError::Message("Unexpected message from client when requesting adapter addresses".to_string()))
-                }
-                Err(e) => {
-                    Err(HEError::Message(format!("Error deserializing message: {}", e)))
+    /// Asks the websocket of the client to close, the client is removed once the connection ends
+    pub fn disconnect(&self) {
+        self.handler_tx.send(Message::Close(None)).ok();
+    }
+
+    /// Waits until the session of the client is over, after [`Client::disconnect`]
+    async fn session_ended(&self) {
+        let mut session_rx = self.session_rx.clone();
+        while session_rx.changed().await.is_ok() {}
+    }
+
+    /// Waits for the addresses being stored by [`Client::get_adapter_addresses`] and stops storing any more
+    async fn stop_recording(&self) {
+        *self.recording.write().await = false;
+    }
+
+    /// The addresses the client reported last, if it did already
+    pub async fn latest_adapter_addresses(&self) -> Option<Vec<IpAddresses>> {
+        self.latest_addresses.read().await.as_ref().map(|snapshot| snapshot.adapter_addresses.clone())
+    }
+
+    async fn get_adapter_addresses(&self, state: &AppState) -> Result<Vec<IpAddresses>, HEError> {
+        let mut request = self.pending_requests.register();
+        let addr_request = MessagePack::AddrRequest { request_id: Some(request.id) };
+        self.handler_tx.send(addr_request.to_framework_message_for(self.protocol_version, self.encoding))?;
+        match (&mut request.rx).await {
+            Ok(MessagePack::AddrResponse { adapter_addresses, host_info, public_addresses, .. }) => {
+                let recording = self.recording.read().await;
+                if !*recording {
+                    return Err(HEError::Message("Client is being deleted, its adapter addresses are not stored".to_string()));
                 }
+                save_new_client_information(&self.id, &state.db, &state.default_offset).await?;
+                record_addresses(&self.id, &self.latest_addresses, adapter_addresses.clone(), host_info, &public_addresses, state).await?;
+                Ok(adapter_addresses)
+            }
+            Ok(MessagePack::Error { message, .. }) => {
+                Err(HEError::Message(format!("Client failed to report its adapter addresses: {}", message)))
+            }
+            Ok(_) => {
+                Err(HEError::Message("Unexpected message from client when requesting adapter addresses".to_string()))
+            }
+            Err(_) => {
+                Err(HEError::Message("Connection closed before the client reported its adapter addresses".to_string()))
             }
-        } else {
-            Err(HEError::Message("Expected adapter addresses message, received nothing".to_string()))
         }
     }
 }
 
+/// Caches the reported addresses and keeps them in the address history of the client,
+/// along with the host information and the public addresses if the client sent them,
+/// then publishes the DNS records mapped to the client
+async fn record_addresses(
+    client_id: &Uuid,
+    latest_addresses: &RwLock<Option<AddressSnapshot>>,
+    adapter_addresses: Vec<IpAddresses>,
+    host_info: Option<HostInfo>,
+    public_addresses: &[IpAddr],
+    state: &AppState,
+) -> Result<(), HEError> {
+    let AppState { db, default_offset, .. } = state;
+    *latest_addresses.write().await = Some(AddressSnapshot::new(adapter_addresses.clone(), default_offset));
+    db::client::update_clients_fetch_time(&[*client_id], db, default_offset).await?;
+    if let Some(host_info) = host_info {
+        db::client::update_host_info(client_id, &host_info, db, default_offset).await?;
+    }
+    db::client::update_public_addresses(client_id, public_addresses, db).await?;
+    let changed = db::address_history::record_addresses(client_id, &adapter_addresses, db).await?;
+    if changed {
+        state.events.send(ClientEvent::AddressChanged {
+            client_id: *client_id,
+            adapter_addresses: adapter_addresses.clone(),
+            time: local_offset_date_time(default_offset),
+        }).ok();
+    }
+    dns::spawn_publish_client_records(*client_id, adapter_addresses, state.clone());
+    Ok(())
+}
+
 pub type Clients = Arc<RwLock<HashMap<Uuid, Client>>>;
 
-pub async fn handle_expose_websocket(ws: WebSocketUpgrade, State(state): State<AppState>) -> impl IntoResponse {
+pub async fn handle_expose_websocket(
+    ws: WebSocketUpgrade,
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+    State(state): State<AppState>,
+) -> impl IntoResponse {
+    let peer_address = resolve_peer_address(peer, &headers, &state.trusted_proxies);
+    // banned addresses are refused before upgrading, sparing the websocket altogether
+    if let Err(e) = state.auth_limiter.check(&peer_address) {
+        state.metrics.record_connection_error(&e);
+        return e.into_response();
+    }
     ws.on_upgrade(move |socket| async move {
-        handle_connection(socket, state.clients, state.db, state.server_base64_password, state.default_offset).await
+        handle_connection(socket, peer_address, state).await
     })
 }
 
-pub async fn handle_connection(ws: WebSocket, clients: Clients, db: DatabaseConnection, server_password: String, default_offset: UtcOffset) {
+/// What a client told about itself in a successful handshake
+struct Handshake {
+    id: Uuid,
+    protocol_version: u32,
+    capabilities: Vec<Capability>,
+    name: Option<String>,
+    tags: Vec<String>,
+    /// Authenticated with the server password rather than a token
+    password_client: bool,
+}
+
+/// Waits for the `Establish` message, checking the protocol version and the credentials of the client
+async fn receive_handshake(ws_rx: &mut SplitStream<WebSocket>, peer_address: &IpAddr, state: &AppState) -> Result<Handshake, HEError> {
+    let text = loop {
+        match ws_rx.next().await {
+            Some(Ok(Message::Text(text))) => break text,
+            Some(Ok(Message::Ping(_) | Message::Pong(_))) => continue,
+            Some(Ok(Message::Binary(_))) => {
+                return Err(HEError::UnexpectedMessage("the Establish message must be sent as JSON text".to_string()));
+            }
+            Some(Ok(Message::Close(_))) | None => {
+                return Err(HEError::Message("connection closed before the handshake".to_string()));
+            }
+            Some(Err(e)) => return Err(HEError::Message(format!("websocket receive error: {}", e))),
+        }
+    };
+    debug!("Received message: {}", text);
+    let pack = MessagePack::from_str(&text)
+        .map_err(|e| HEError::MalformedMessage(format!("invalid Establish message: {}", e)))?;
+    let MessagePack::Establish { id, protocol_version, capabilities, password, token, name, tags } = pack else {
+        return Err(HEError::UnexpectedMessage(format!("expected an Establish message, received {:?}", pack)));
+    };
+    let Some(protocol_version) = negotiate_protocol_version(protocol_version) else {
+        return Err(HEError::IncompatibleProtocol(format!(
+            "Protocol version {} is not supported by this server, which speaks version {}, please upgrade the client",
+            protocol_version, PROTOCOL_VERSION,
+        )));
+    };
+    let password_client = token.is_none();
+    let authorized = match token {
+        Some(token) => db::client_token::verify_token(&id, &token, &state.db, &state.default_offset).await
+            .unwrap_or_else(|e| {
+                error!("Failed to verify token of client {}: {:?}", &id, e);
+                false
+            }),
+        None => state.allow_password_clients && state.password_verifier.verify_base64(&password).await,
+    };
+    if !authorized {
+        warn!("Client {} from {} failed to authenticate", &id, peer_address);
+        state.auth_limiter.record_failure(peer_address);
+        return Err(HEError::Unauthorized("Invalid token or password".to_string()));
+    }
+    let capabilities = capabilities.into_iter()
+        .filter(|capability| SERVER_CAPABILITIES.contains(capability))
+        .collect();
+    Ok(Handshake { id, protocol_version, capabilities, name, tags, password_client })
+}
+
+/// Tells the client about a message the server could not handle, keeping the connection open
+fn report_connection_error(client_id: &Uuid, error: HEError, handler_tx: &mpsc::UnboundedSender<Message>, encoding: Encoding, state: &AppState) {
+    warn!("client {}: {}", client_id, error);
+    state.metrics.record_connection_error(&error);
+    handler_tx.send(MessagePack::Error { message: error.to_string(), request_id: None }.to_framework_message_in(encoding)).ok();
+}
+
+/// Tells the client why the connection is ended, before closing it with the matching close code.
+/// Errors not caused by the client are not detailed to it.
+async fn reject(ws_tx: &mut SplitSink<WebSocket, Message>, error: &HEError) {
+    let message = if error.is_client_error() {
+        error.to_string()
+    } else {
+        "internal server error".to_string()
+    };
+    ws_tx.send(MessagePack::Error { message, request_id: None }.to_framework_message()).await.ok();
+    ws_tx.send(Message::Close(Some(CloseFrame {
+        code: error.close_code(),
+        reason: error.kind().into(),
+    }))).await.ok();
+}
+
+pub async fn handle_connection(ws: WebSocket, peer_address: IpAddr, state: AppState) {
+    let AppState { clients, db, default_offset, .. } = state.clone();
     let (mut ws_tx, mut ws_rx) = ws.split();
 
     let (handler_tx, handler_rx) = mpsc::unbounded_channel();
     let mut handler_rx = UnboundedReceiverStream::new(handler_rx);
 
-    let (client_tx, client_rx) = mpsc::unbounded_channel();
-    let client_rx = UnboundedReceiverStream::new(client_rx);
-    let client_id;
-    if let Some(message) = ws_rx.next().await {
-        let msg = message.unwrap();
-        let text = msg.to_text().unwrap();
-        debug!("Received message: {}", text);
-        match MessagePack::from_str(text).unwrap() {
-            MessagePack::Establish { id, password } => {
-                if password != server_password {
-                    ws_tx.send(MessagePack::Error { message: "Invalid password".to_string() }.to_framework_message()).await.unwrap();
-                    return;
-                }
-                client_id = id;
-                clients.write().await.insert(id, Client { id, handler_tx, client_rx });
-                info!("Establishing connection with id: {}", &id);
-                ws_tx.send(MessagePack::Acknowledge.to_framework_message()).await.unwrap();
-                if let Err(e) = save_new_client_information(&id, &db, &default_offset).await {
-                    error!("Failed to save new client information: {:?}", e);
-                    return;
-                }
-            }
-            pack => {
-                error!("Unexpected message: {:?} from client when establishing connection, expected Establish message.", pack);
-                return;
-            }
+    let latest_addresses = Arc::new(RwLock::new(None));
+    // dropped when this function returns, telling that the session will not touch the client anymore
+    let (session_tx, session_rx) = watch::channel(());
+    let handshake = tokio::time::timeout(state.handshake_timeout, receive_handshake(&mut ws_rx, &peer_address, &state)).await
+        .unwrap_or_else(|_| Err(HEError::Timeout(format!("no Establish message within {:?}", state.handshake_timeout))));
+    let Handshake { id: client_id, protocol_version, capabilities, name, tags, password_client } = match handshake {
+        Ok(handshake) => handshake,
+        Err(e) => {
+            warn!("Handshake with {} failed: {}", &peer_address, e);
+            state.metrics.record_connection_error(&e);
+            reject(&mut ws_tx, &e).await;
+            return;
         }
-    } else {
-        error!("Expected text established message, received something else");
+    };
+    let pending_requests = PendingRequests::new(protocol_version < 2);
+    if let Err(e) = save_new_client_information(&client_id, &db, &default_offset).await {
+        error!("Failed to save new client information: {:?}", e);
+        state.metrics.record_connection_error(&e);
+        reject(&mut ws_tx, &e).await;
+        return;
+    }
+    let acknowledge = MessagePack::Acknowledge {
+        protocol_version: PROTOCOL_VERSION,
+        capabilities: SERVER_CAPABILITIES.to_vec(),
+    };
+    if let Err(e) = ws_tx.send(acknowledge.to_framework_message_for(protocol_version, Encoding::Json)).await {
+        error!("Failed to acknowledge client {}: {}", &client_id, e);
         return;
     }
+    let encoding = Encoding::negotiate(&SERVER_CAPABILITIES, &capabilities);
+    clients.write().await.insert(client_id, Client {
+        id: client_id,
+        protocol_version,
+        capabilities,
+        encoding,
+        handler_tx: handler_tx.clone(),
+        pending_requests: pending_requests.clone(),
+        latest_addresses: latest_addresses.clone(),
+        password_client,
+        session_rx,
+        recording: Arc::new(RwLock::new(true)),
+    });
+    info!("Establishing connection with id: {} from {} over protocol version {}", &client_id, &peer_address, protocol_version);
+    if let Err(e) = db::client::update_peer_address(&client_id, &peer_address, &db).await {
+        error!("Failed to save peer address of client {}: {:?}", &client_id, e);
+    }
+    if let Err(e) = db::client::update_last_seen_time(&client_id, &db).await {
+        error!("Failed to save last seen time of client {}: {:?}", &client_id, e);
+    }
+    // a client coming back is not decommissioned after all
+    if let Err(e) = db::client::set_archived(&client_id, false, &db).await {
+        error!("Failed to unarchive client {}: {:?}", &client_id, e);
+    }
+    if let Err(e) = apply_declared_labels(&client_id, name, &tags, &state).await {
+        error!("Failed to apply the name and tags declared by client {}: {:?}", &client_id, e);
+    }
+    state.events.send(ClientEvent::Connected {
+        client_id,
+        peer_address,
+        time: local_offset_date_time(&default_offset),
+    }).ok();
 
     tokio::spawn(async move {
         while let Some(message) = handler_rx.next().await {
@@ -111,7 +400,26 @@ pub async fn handle_connection(ws: WebSocket, clients: Clients, db: DatabaseConn
         }
     });
 
-    while let Some(result) = ws_rx.next().await {
+    let mut heartbeat = tokio::time::interval(state.heartbeat_interval);
+    heartbeat.reset();
+    let mut last_received = Instant::now();
+    loop {
+        let result = tokio::select! {
+            result = ws_rx.next() => result,
+            _ = heartbeat.tick() => {
+                if last_received.elapsed() > state.heartbeat_timeout {
+                    warn!("client {} sent nothing for {:?}, considering the connection dead", &client_id, last_received.elapsed());
+                    handler_tx.send(Message::Close(None)).ok();
+                    break;
+                }
+                handler_tx.send(Message::Ping(Vec::new())).ok();
+                continue;
+            }
+        };
+        let Some(result) = result else {
+            break;
+        };
+        last_received = Instant::now();
         let msg = match result {
             Ok(msg) => msg,
             Err(e) => {
@@ -119,45 +427,193 @@ pub async fn handle_connection(ws: WebSocket, clients: Clients, db: DatabaseConn
                 break;
             }
         };
-        client_tx.send(msg).unwrap()
+        // clients may send either encoding, whatever was negotiated
+        let pack = match msg {
+            Message::Text(text) => {
+                debug!("Received message from client {}: {}", &client_id, text);
+                MessagePack::from_str(&text).map_err(|e| HEError::MalformedMessage(e.to_string()))
+            }
+            Message::Binary(bytes) => {
+                let pack = MessagePack::from_binary(&bytes).map_err(|e| HEError::MalformedMessage(e.to_string()));
+                debug!("Received binary message of {} bytes from client {}: {:?}", bytes.len(), &client_id, pack);
+                pack
+            }
+            Message::Close(_) => break,
+            Message::Ping(_) | Message::Pong(_) => continue,
+        };
+        match pack {
+            Ok(MessagePack::AddrUpdate { adapter_addresses, host_info, public_addresses }) => {
+                if let Err(e) = record_addresses(&client_id, &latest_addresses, adapter_addresses, host_info, &public_addresses, &state).await {
+                    error!("Failed to record addresses of client {}: {:?}", &client_id, e);
+                }
+            }
+            Ok(pack @ MessagePack::AddrResponse { request_id, .. }) => {
+                // a response arriving after its request was given up on still tells the current addresses
+                if let Some(MessagePack::AddrResponse { adapter_addresses, host_info, public_addresses, .. }) = pending_requests.resolve(request_id, pack) {
+                    debug!("client {} answered request {:?} which is no longer pending", &client_id, request_id);
+                    if let Err(e) = record_addresses(&client_id, &latest_addresses, adapter_addresses, host_info, &public_addresses, &state).await {
+                        error!("Failed to record addresses of client {}: {:?}", &client_id, e);
+                    }
+                }
+            }
+            Ok(pack @ MessagePack::Error { request_id: Some(request_id), .. }) => {
+                if let Some(MessagePack::Error { message, .. }) = pending_requests.resolve(Some(request_id), pack) {
+                    warn!("client {} failed request {} which is no longer pending: {}", &client_id, request_id, message);
+                }
+            }
+            Ok(MessagePack::Error { message, request_id: None }) => error!("client {} sent an error: {}", &client_id, message),
+            Ok(pack) => {
+                let e = HEError::UnexpectedMessage(format!("{:?} is not expected from a client", pack));
+                report_connection_error(&client_id, e, &handler_tx, encoding, &state);
+            }
+            Err(e) => report_connection_error(&client_id, e, &handler_tx, encoding, &state),
+        }
     }
 
-    clients.write().await.remove(&client_id);
+    pending_requests.clear();
+    let removed = {
+        let mut clients = clients.write().await;
+        // the client may have already reconnected through a new connection, which must be kept
+        let current = clients.get(&client_id).is_some_and(|client| client.handler_tx.same_channel(&handler_tx));
+        if current {
+            clients.remove(&client_id);
+        }
+        current
+    };
+    if removed {
+        if let Err(e) = db::client::update_last_seen_time(&client_id, &db).await {
+            error!("Failed to save last seen time of client {}: {:?}", &client_id, e);
+        }
+        state.events.send(ClientEvent::Disconnected {
+            client_id,
+            time: local_offset_date_time(&default_offset),
+        }).ok();
+    }
+    info!("Connection with id: {} closed", &client_id);
+    drop(session_tx);
 }
 
-pub async fn get_clients_information(State(state): State<AppState>) -> Result<Json<Vec<Value>>, HEError> {
-    let mut clients = state.clients.write().await;
-    let mut clients_info: Vec<Value> = Vec::new();
-    let all_client_ids: Vec<Uuid> = clients.keys()
-        .copied()
+#[derive(Deserialize)]
+pub struct ClientsInformationQuery {
+    /// Request fresh addresses from every connected client instead of answering from the cache
+    #[serde(default)]
+    refresh: bool,
+    /// Also list the archived clients
+    #[serde(default)]
+    archived: bool,
+    /// Comma separated tags, only the clients having all of them are listed
+    #[serde(default)]
+    tag: Option<String>,
+    /// Only list the clients in this group
+    #[serde(default)]
+    group: Option<String>,
+}
+
+pub async fn get_clients_information(
+    State(state): State<AppState>,
+    Query(query): Query<ClientsInformationQuery>,
+) -> Result<Json<Vec<Value>>, HEError> {
+    let clients: Vec<Client> = state.clients.read().await.values()
+        .cloned()
         .collect();
     let db = &state.db;
-    let default_offset = &state.default_offset;
-    let target_clients: HashMap<Uuid, client::Model> = DbClient::find()
-        .filter(client::Column::Id.is_in(all_client_ids))
-        .all(db).await?
-        .into_iter()
-        .map(|mut client| {
-            client.last_fetch_time = local_offset_date_time(default_offset);
-            (client.id, client)
-        })
+    if query.refresh {
+        refresh_adapter_addresses(&clients, &state).await;
+    }
+    let online_clients: HashMap<Uuid, Client> = clients.into_iter()
+        .map(|client| (client.id, client))
         .collect();
-    let mut updated_client_ids: Vec<Uuid> = Vec::new();
-    for (id, client) in clients.iter_mut() {
-        let client_adapter_addresses = client.get_adapter_addresses(db, default_offset).await.unwrap_or_else(|e| {
-            error!("Failed to get adapter addresses: {:?}", e);
-            vec![IpAddresses::empty(e.to_string())]
-        });
+    let tags = match &query.tag {
+        Some(tag) => tag.split(',').map(normalize_tag).collect::<Result<Vec<_>, _>>()?,
+        None => Vec::new(),
+    };
+    let db_clients = db::client::find_clients(query.archived, &tags, query.group.as_deref(), db).await?;
+    let ids: Vec<Uuid> = db_clients.iter().map(|db_client| db_client.id).collect();
+    let mut client_tags = db::client_tag::find_tags(&ids, db).await?;
+    let mut latest_addresses = db::address_history::find_latest_addresses_of(&ids, db).await?;
+    let mut clients_info: Vec<Value> = Vec::with_capacity(db_clients.len());
+    // times stored in UTC are shown with the local offset like the other times
+    let local_offset = local_offset_date_time(&state.default_offset).offset();
+    for mut db_client in db_clients {
+        db_client.last_seen_time = db_client.last_seen_time.map(|time| time.to_offset(local_offset));
+        let online_client = online_clients.get(&db_client.id);
+        let snapshot = match online_client {
+            Some(client) => client.latest_addresses.read().await.clone(),
+            None => None,
+        };
+        // offline clients, and those which did not report yet, show what they reported last
+        let (adapter_addresses, reported_at) = match snapshot {
+            Some(snapshot) => (snapshot.adapter_addresses, Some(snapshot.reported_at)),
+            None => match latest_addresses.remove(&db_client.id) {
+                Some(latest) => (
+                    serde_json::from_value(latest.addresses).unwrap_or_default(),
+                    Some(latest.last_seen_time.to_offset(local_offset)),
+                ),
+                None => (Vec::new(), None),
+            },
+        };
+        let tags = client_tags.remove(&db_client.id).unwrap_or_default();
         clients_info.push(json!({
-            "entity": target_clients.get(id).unwrap(),
-            "adapter_addresses": client_adapter_addresses
+            "entity": db_client,
+            "tags": tags,
+            "online": online_client.is_some(),
+            "protocol_version": online_client.map(|client| client.protocol_version),
+            "capabilities": online_client.map(|client| client.capabilities.clone()),
+            "adapter_addresses": adapter_addresses,
+            "reported_at": reported_at
         }));
-        updated_client_ids.push(*id);
     }
-    db::client::update_clients_fetch_time(updated_client_ids.as_slice(), db, default_offset).await?;
     Ok(Json(clients_info))
 }
 
+/// Takes the name and tags the client declared in its handshake, unless an admin has set them
+/// and the server is not configured to let the clients override them
+async fn apply_declared_labels(id: &Uuid, name: Option<String>, tags: &[String], state: &AppState) -> Result<(), HEError> {
+    let override_admin = state.client_labels_override;
+    if let Some(name) = non_empty(name) {
+        if db::client::update_declared_name(id, &name, override_admin, &state.db).await? {
+            info!("Client {} declared its name as {}", id, &name);
+            state.events.send(ClientEvent::Renamed {
+                client_id: *id,
+                name,
+                time: local_offset_date_time(&state.default_offset),
+            }).ok();
+        }
+    }
+    if tags.is_empty() {
+        return Ok(());
+    }
+    let Some(db_client) = DbClient::find_by_id(*id).one(&state.db).await? else {
+        return Ok(());
+    };
+    if db_client.tags_set_by_admin && !override_admin {
+        return Ok(());
+    }
+    let mut valid_tags = Vec::with_capacity(tags.len());
+    for tag in tags {
+        match normalize_tag(tag) {
+            Ok(tag) => valid_tags.push(tag),
+            Err(e) => warn!("Ignoring tag declared by client {}: {:?}", id, e),
+        }
+    }
+    valid_tags.sort();
+    valid_tags.dedup();
+    db::client_tag::replace_tags(id, &valid_tags, &state.db).await?;
+    db::client::set_tags_set_by_admin(id, false, &state.db).await?;
+    Ok(())
+}
+
+/// Requests the addresses of all the given clients concurrently, the results land in their caches.
+async fn refresh_adapter_addresses(clients: &[Client], state: &AppState) {
+    future::join_all(clients.iter().map(|client| async move {
+        match tokio::time::timeout(state.refresh_timeout, client.get_adapter_addresses(state)).await {
+            Ok(Ok(_)) => {}
+            Ok(Err(e)) => error!("Failed to get adapter addresses of client {}: {:?}", &client.id, e),
+            Err(_) => warn!("Timed out getting adapter addresses of client {}", &client.id),
+        }
+    })).await;
+}
+
 #[derive(Deserialize)]
 pub struct ModifyClientNameBody {
     new_name: String,
@@ -169,7 +625,253 @@ pub async fn modify_client_name(
     Json(body): Json<ModifyClientNameBody>,
 ) -> Result<(), HEError> {
     let db = &state.db;
-    db::client::modify_client_name(&id, body.new_name, db).await?;
+    db::client::modify_client_name(&id, body.new_name.clone(), db).await?;
+    state.events.send(ClientEvent::Renamed {
+        client_id: id,
+        name: body.new_name,
+        time: local_offset_date_time(&state.default_offset),
+    }).ok();
+    Ok(())
+}
+
+/// Tags are compared case-insensitively, and may not contain commas as the listing filter is comma separated
+fn normalize_tag(tag: &str) -> Result<String, HEError> {
+    let tag = tag.trim().to_lowercase();
+    if tag.is_empty() || tag.len() > 64 || tag.contains(',') {
+        return Err(HEError::BadRequest(format!("'{}' is not a valid tag, tags must be 1 to 64 characters without commas", tag)));
+    }
+    Ok(tag)
+}
+
+/// Empty strings clear the field
+fn non_empty(value: Option<String>) -> Option<String> {
+    value.map(|value| value.trim().to_string()).filter(|value| !value.is_empty())
+}
+
+#[derive(Deserialize)]
+pub struct ModifyClientTagsBody {
+    tags: Vec<String>,
+}
+
+/// Replaces the tags of the client, returning them as stored
+pub async fn modify_client_tags(
+    State(state): State<AppState>,
+    Path(id): Path<Uuid>,
+    Json(body): Json<ModifyClientTagsBody>,
+) -> Result<Json<Vec<String>>, HEError> {
+    let mut tags = body.tags.iter().map(|tag| normalize_tag(tag)).collect::<Result<Vec<_>, _>>()?;
+    tags.sort();
+    tags.dedup();
+    if DbClient::find_by_id(id).one(&state.db).await?.is_none() {
+        return Err(HEError::NotFound(format!("client {} not found", id)));
+    }
+    db::client_tag::replace_tags(&id, &tags, &state.db).await?;
+    db::client::set_tags_set_by_admin(&id, true, &state.db).await?;
+    Ok(Json(tags))
+}
+
+#[derive(Deserialize)]
+pub struct ModifyClientGroupBody {
+    #[serde(default)]
+    group: Option<String>,
+}
+
+pub async fn modify_client_group(
+    State(state): State<AppState>,
+    Path(id): Path<Uuid>,
+    Json(body): Json<ModifyClientGroupBody>,
+) -> Result<(), HEError> {
+    if !db::client::update_group(&id, non_empty(body.group), &state.db).await? {
+        return Err(HEError::NotFound(format!("client {} not found", id)));
+    }
+    Ok(())
+}
+
+#[derive(Deserialize)]
+pub struct ModifyClientNotesBody {
+    #[serde(default)]
+    notes: Option<String>,
+}
+
+pub async fn modify_client_notes(
+    State(state): State<AppState>,
+    Path(id): Path<Uuid>,
+    Json(body): Json<ModifyClientNotesBody>,
+) -> Result<(), HEError> {
+    if !db::client::update_notes(&id, non_empty(body.notes), &state.db).await? {
+        return Err(HEError::NotFound(format!("client {} not found", id)));
+    }
+    Ok(())
+}
+
+#[derive(Deserialize)]
+pub struct DeleteClientQuery {
+    /// Disconnect the client if it is online, instead of refusing to delete it
+    #[serde(default)]
+    disconnect: bool,
+}
+
+/// Longest time to wait for the session of a client being deleted to end
+const DISCONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Deletes the client with everything belonging to it, including the tokens it connects with.
+/// Online clients authenticating with the server password are refused, as they would only come back.
+pub async fn delete_client(
+    State(state): State<AppState>,
+    Path(id): Path<Uuid>,
+    Query(query): Query<DeleteClientQuery>,
+) -> Result<(), HEError> {
+    if DbClient::find_by_id(id).one(&state.db).await?.is_none() {
+        return Err(HEError::NotFound(format!("client {} not found", id)));
+    }
+    let online_client = state.clients.read().await.get(&id).cloned();
+    if let Some(client) = &online_client {
+        if !query.disconnect {
+            return Err(HEError::Conflict(format!("client {} is online, pass disconnect=true to disconnect it", id)));
+        }
+        // it would reconnect right away and be created again, as it needs no token
+        if client.password_client {
+            return Err(HEError::Conflict(format!("client {} authenticates with the server password, stop it before deleting it", id)));
+        }
+    }
+    // a client reconnecting before its rows are gone would start recording for it again
+    db::client_token::revoke_client_tokens(&id, &state.db).await?;
+    if let Some(client) = online_client {
+        info!("Disconnecting client {} to delete it", &id);
+        client.disconnect();
+        // the session must not record anything for the client once its rows are gone
+        tokio::time::timeout(DISCONNECT_TIMEOUT, client.session_ended()).await
+            .map_err(|_| HEError::Timeout(format!("client {} did not disconnect within {:?}", id, DISCONNECT_TIMEOUT)))?;
+        client.stop_recording().await;
+    }
+    dns::unpublish_client_records(&id, &state).await?;
+    db::client::delete_client(&id, &state.db).await?;
+    state.dns_publishes.remove(&id);
+    info!("Deleted client {}", &id);
     Ok(())
 }
 
+pub async fn archive_client(
+    State(state): State<AppState>,
+    Path(id): Path<Uuid>,
+) -> Result<(), HEError> {
+    if !db::client::set_archived(&id, true, &state.db).await? {
+        return Err(HEError::NotFound(format!("client {} not found", id)));
+    }
+    Ok(())
+}
+
+pub async fn unarchive_client(
+    State(state): State<AppState>,
+    Path(id): Path<Uuid>,
+) -> Result<(), HEError> {
+    if !db::client::set_archived(&id, false, &state.db).await? {
+        return Err(HEError::NotFound(format!("client {} not found", id)));
+    }
+    Ok(())
+}
+
+/// Archives the clients not seen for `prune_after` once an hour, the online clients being seen right now
+pub fn spawn_client_pruner(state: AppState, prune_after: Duration) {
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(Duration::from_secs(60 * 60));
+        loop {
+            ticker.tick().await;
+            let online_clients: Vec<Uuid> = state.clients.read().await.keys().copied().collect();
+            let threshold = OffsetDateTime::now_utc() - prune_after;
+            match db::client::archive_clients_not_seen_since(threshold, &online_clients, &state.db).await {
+                Ok(archived) => {
+                    for client in archived {
+                        info!("Archived client {} ({}), not seen since {:?}", &client.id, &client.name, client.last_seen_time);
+                    }
+                }
+                Err(e) => error!("Failed to archive stale clients: {:?}", e),
+            }
+        }
+    });
+}
+
+#[derive(Deserialize)]
+pub struct AddressHistoryQuery {
+    #[serde(default, with = "time::serde::rfc3339::option")]
+    from: Option<OffsetDateTime>,
+    #[serde(default, with = "time::serde::rfc3339::option")]
+    to: Option<OffsetDateTime>,
+    /// Page number starting from 0
+    #[serde(default)]
+    page: u64,
+    #[serde(default = "default_history_page_size")]
+    page_size: u64,
+}
+
+fn default_history_page_size() -> u64 {
+    20
+}
+
+pub async fn get_client_address_history(
+    State(state): State<AppState>,
+    Path(id): Path<Uuid>,
+    Query(query): Query<AddressHistoryQuery>,
+) -> Result<Json<Value>, HEError> {
+    let db = &state.db;
+    if DbClient::find_by_id(id).one(db).await?.is_none() {
+        return Err(HEError::NotFound(format!("client {} not found", id)));
+    }
+    let page_size = query.page_size.clamp(1, 100);
+    let (mut entries, total) = db::address_history::find_addresses_history(
+        &id,
+        query.from,
+        query.to,
+        query.page,
+        page_size,
+        db,
+    ).await?;
+    // times are stored in UTC, show them with the local offset like the other times
+    let local_offset = local_offset_date_time(&state.default_offset).offset();
+    for entry in entries.iter_mut() {
+        entry.first_seen_time = entry.first_seen_time.to_offset(local_offset);
+        entry.last_seen_time = entry.last_seen_time.to_offset(local_offset);
+    }
+    Ok(Json(json!({
+        "total": total,
+        "page": query.page,
+        "page_size": page_size,
+        "entries": entries
+    })))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn response(request_id: Option<u64>) -> MessagePack {
+        MessagePack::AddrResponse { request_id, adapter_addresses: Vec::new(), host_info: None, public_addresses: Vec::new() }
+    }
+
+    #[test]
+    fn responses_answer_the_request_with_their_id() {
+        let requests = PendingRequests::new(false);
+        let mut first = requests.register();
+        let mut second = requests.register();
+        assert!(requests.resolve(Some(second.id), response(Some(second.id))).is_none());
+        assert!(second.rx.try_recv().is_ok());
+        assert!(first.rx.try_recv().is_err());
+        let first_id = first.id;
+        drop(first);
+        // given up on, so handed back
+        assert!(requests.resolve(Some(first_id), response(Some(first_id))).is_some());
+    }
+
+    #[test]
+    fn late_response_without_id_does_not_answer_the_next_request() {
+        let requests = PendingRequests::new(true);
+        // timed out before the client answered
+        drop(requests.register());
+        let mut next = requests.register();
+        assert!(requests.resolve(None, response(None)).is_some());
+        assert!(next.rx.try_recv().is_err());
+        assert!(requests.resolve(None, response(None)).is_none());
+        assert!(next.rx.try_recv().is_ok());
+        assert!(requests.resolve(None, response(None)).is_some());
+    }
+}
@@ -5,27 +5,35 @@ use tracing::log;
 use crate::migration::Migrator;
 use crate::result::HEError;
 
-pub async fn setup_db_connection() -> Result<DatabaseConnection, HEError> {
-    let mut opt = ConnectOptions::new("sqlite://data.sqlite?mode=rwc".to_owned());
-    opt.sqlx_logging_level(log::LevelFilter::Debug);
-    let db = Database::connect(opt).await?;
+pub async fn setup_db_connection(database_url: &str) -> Result<DatabaseConnection, HEError> {
+    let db = connect_db(database_url).await?;
     Migrator::up(&db, None).await?;
     Ok(db)
 }
 
+pub async fn connect_db(database_url: &str) -> Result<DatabaseConnection, HEError> {
+    let mut opt = ConnectOptions::new(database_url.to_owned());
+    opt.sqlx_logging_level(log::LevelFilter::Debug);
+    Ok(Database::connect(opt).await?)
+}
+
 pub(crate) mod client {
-    use sea_orm::{ActiveModelTrait, ColumnTrait, DatabaseConnection, EntityTrait, QueryFilter};
+    use std::net::IpAddr;
+
+    use sea_orm::{ActiveModelTrait, ColumnTrait, ConnectionTrait, DatabaseConnection, EntityTrait, QueryFilter, QueryOrder};
     use sea_orm::ActiveValue::Set;
     use sea_orm::prelude::Expr;
-    use time::UtcOffset;
+    use sea_orm::sea_query::Query;
+    use time::{Duration, OffsetDateTime, UtcOffset};
     use uuid::Uuid;
+    use public_lib::message::HostInfo;
     use public_lib::times::local_offset_date_time;
 
-    use crate::entity::client;
+    use crate::entity::{client, client_tag};
     use crate::entity::prelude::DbClient;
     use crate::result::HEError;
 
-    pub async fn save_new_client_information(id: &Uuid, db: &DatabaseConnection, default_offset: &UtcOffset) -> Result<(), HEError> {
+    pub async fn save_new_client_information(id: &Uuid, db: &impl ConnectionTrait, default_offset: &UtcOffset) -> Result<(), HEError> {
         let db_client = DbClient::find_by_id(*id).one(db).await?;
         if db_client.is_none() {
             let now = local_offset_date_time(default_offset);
@@ -34,6 +42,11 @@ pub(crate) mod client {
                 name: Set(id.to_string()),
                 create_time: Set(now),
                 last_fetch_time: Set(now),
+                last_seen_time: Set(Some(OffsetDateTime::now_utc())),
+                archived: Set(false),
+                name_set_by_admin: Set(false),
+                tags_set_by_admin: Set(false),
+                ..Default::default()
             };
             if let Err(db_err) = new_client.insert(db).await {
                 return Err(HEError::Db(db_err));
@@ -42,20 +55,579 @@ pub(crate) mod client {
         Ok(())
     }
 
+    /// Having fetched the addresses of the clients, they were also last seen just now
     pub async fn update_clients_fetch_time(ids: &[Uuid], db: &DatabaseConnection, default_offset: &UtcOffset) -> Result<(), HEError> {
         DbClient::update_many()
             .col_expr(client::Column::LastFetchTime, Expr::value(local_offset_date_time(default_offset)))
+            .col_expr(client::Column::LastSeenTime, Expr::value(Some(OffsetDateTime::now_utc())))
             .filter(client::Column::Id.is_in(ids.iter().copied()))
             .exec(db)
             .await?;
         Ok(())
     }
 
-    pub async fn modify_client_name(id: &Uuid, new_name: String, db: &DatabaseConnection) -> Result<(), HEError> {
+    /// The last seen time is stored in UTC so the stale clients are found whatever the local offset was
+    pub async fn update_last_seen_time(id: &Uuid, db: &DatabaseConnection) -> Result<(), HEError> {
+        DbClient::update_many()
+            .col_expr(client::Column::LastSeenTime, Expr::value(Some(OffsetDateTime::now_utc())))
+            .filter(client::Column::Id.eq(*id))
+            .exec(db)
+            .await?;
+        Ok(())
+    }
+
+    /// Finds the clients in the group, if given, having all the given tags
+    pub async fn find_clients(include_archived: bool, tags: &[String], group: Option<&str>, db: &DatabaseConnection) -> Result<Vec<client::Model>, HEError> {
+        let mut query = DbClient::find();
+        if !include_archived {
+            query = query.filter(client::Column::Archived.eq(false));
+        }
+        for tag in tags {
+            query = query.filter(client::Column::Id.in_subquery(
+                Query::select()
+                    .column(client_tag::Column::ClientId)
+                    .from(client_tag::Entity)
+                    .and_where(client_tag::Column::Tag.eq(tag.as_str()))
+                    .to_owned()
+            ));
+        }
+        if let Some(group) = group {
+            query = query.filter(client::Column::Group.eq(group));
+        }
+        Ok(query
+            .order_by_asc(client::Column::CreateTime)
+            .all(db)
+            .await?)
+    }
+
+    /// Returns whether the client exists
+    pub async fn set_archived(id: &Uuid, archived: bool, db: &DatabaseConnection) -> Result<bool, HEError> {
+        let result = DbClient::update_many()
+            .col_expr(client::Column::Archived, Expr::value(archived))
+            .filter(client::Column::Id.eq(*id))
+            .exec(db)
+            .await?;
+        Ok(result.rows_affected > 0)
+    }
+
+    /// Archives the clients not seen since `time`, except the given ones, returning the archived ones
+    pub async fn archive_clients_not_seen_since(time: OffsetDateTime, except: &[Uuid], db: &DatabaseConnection) -> Result<Vec<client::Model>, HEError> {
+        let stale_clients = DbClient::find()
+            .filter(client::Column::Archived.eq(false))
+            .filter(client::Column::LastSeenTime.lt(time.to_offset(UtcOffset::UTC)))
+            .filter(client::Column::Id.is_not_in(except.iter().copied()))
+            .all(db)
+            .await?;
+        if !stale_clients.is_empty() {
+            DbClient::update_many()
+                .col_expr(client::Column::Archived, Expr::value(true))
+                .filter(client::Column::Id.is_in(stale_clients.iter().map(|client| client.id)))
+                .exec(db)
+                .await?;
+        }
+        Ok(stale_clients)
+    }
+
+    /// Deletes the client along with its history, tokens and DNS records, returns the deleted client
+    pub async fn delete_client(id: &Uuid, db: &DatabaseConnection) -> Result<Option<client::Model>, HEError> {
         let db_client = DbClient::find_by_id(*id).one(db).await?;
-        let mut db_client: client::ActiveModel = db_client.unwrap().into();
+        if db_client.is_some() {
+            DbClient::delete_by_id(*id).exec(db).await?;
+        }
+        Ok(db_client)
+    }
+
+    /// Stores the latest description of the machine the client runs on
+    pub async fn update_host_info(id: &Uuid, host_info: &HostInfo, db: &DatabaseConnection, default_offset: &UtcOffset) -> Result<(), HEError> {
+        let boot_time = local_offset_date_time(default_offset) - Duration::seconds(host_info.uptime as i64);
+        DbClient::update_many()
+            .col_expr(client::Column::Hostname, Expr::value(host_info.hostname.clone()))
+            .col_expr(client::Column::OsVersion, Expr::value(host_info.os_version.clone()))
+            .col_expr(client::Column::KernelVersion, Expr::value(host_info.kernel_version.clone()))
+            .col_expr(client::Column::Arch, Expr::value(host_info.arch.clone()))
+            .col_expr(client::Column::ClientVersion, Expr::value(host_info.client_version.clone()))
+            .col_expr(client::Column::BootTime, Expr::value(boot_time))
+            .filter(client::Column::Id.eq(*id))
+            .exec(db)
+            .await?;
+        Ok(())
+    }
+
+    /// Stores the address the client connected from, as resolved behind the trusted proxies
+    pub async fn update_peer_address(id: &Uuid, peer_address: &IpAddr, db: &DatabaseConnection) -> Result<(), HEError> {
+        DbClient::update_many()
+            .col_expr(client::Column::PeerAddress, Expr::value(peer_address.to_string()))
+            .filter(client::Column::Id.eq(*id))
+            .exec(db)
+            .await?;
+        Ok(())
+    }
+
+    /// Stores the addresses the client discovered for itself through STUN
+    pub async fn update_public_addresses(id: &Uuid, public_addresses: &[IpAddr], db: &DatabaseConnection) -> Result<(), HEError> {
+        let public_addresses = serde_json::to_value(public_addresses)
+            .map_err(|e| HEError::Message(format!("Error serializing public addresses: {}", e)))?;
+        DbClient::update_many()
+            .col_expr(client::Column::PublicAddresses, Expr::value(public_addresses))
+            .filter(client::Column::Id.eq(*id))
+            .exec(db)
+            .await?;
+        Ok(())
+    }
+
+    /// Returns whether the client exists
+    pub async fn update_group(id: &Uuid, group: Option<String>, db: &DatabaseConnection) -> Result<bool, HEError> {
+        let result = DbClient::update_many()
+            .col_expr(client::Column::Group, Expr::value(group))
+            .filter(client::Column::Id.eq(*id))
+            .exec(db)
+            .await?;
+        Ok(result.rows_affected > 0)
+    }
+
+    /// Returns whether the client exists
+    pub async fn update_notes(id: &Uuid, notes: Option<String>, db: &DatabaseConnection) -> Result<bool, HEError> {
+        let result = DbClient::update_many()
+            .col_expr(client::Column::Notes, Expr::value(notes))
+            .filter(client::Column::Id.eq(*id))
+            .exec(db)
+            .await?;
+        Ok(result.rows_affected > 0)
+    }
+
+    pub async fn modify_client_name(id: &Uuid, new_name: String, db: &DatabaseConnection) -> Result<(), HEError> {
+        let Some(db_client) = DbClient::find_by_id(*id).one(db).await? else {
+            return Err(HEError::NotFound(format!("client {} not found", id)));
+        };
+        let mut db_client: client::ActiveModel = db_client.into();
         db_client.name = Set(new_name);
+        db_client.name_set_by_admin = Set(true);
         db_client.update(db).await?;
         Ok(())
     }
-}
\ No newline at end of file
+
+    /// Takes the name declared by the client, returns whether it changed
+    pub async fn update_declared_name(id: &Uuid, name: &str, override_admin: bool, db: &DatabaseConnection) -> Result<bool, HEError> {
+        let mut query = DbClient::update_many()
+            .col_expr(client::Column::Name, Expr::value(name))
+            .col_expr(client::Column::NameSetByAdmin, Expr::value(false))
+            .filter(client::Column::Id.eq(*id))
+            .filter(client::Column::Name.ne(name));
+        if !override_admin {
+            query = query.filter(client::Column::NameSetByAdmin.eq(false));
+        }
+        Ok(query.exec(db).await?.rows_affected > 0)
+    }
+
+    pub async fn set_tags_set_by_admin(id: &Uuid, set_by_admin: bool, db: &DatabaseConnection) -> Result<(), HEError> {
+        DbClient::update_many()
+            .col_expr(client::Column::TagsSetByAdmin, Expr::value(set_by_admin))
+            .filter(client::Column::Id.eq(*id))
+            .exec(db)
+            .await?;
+        Ok(())
+    }
+}
+
+pub(crate) mod client_tag {
+    use std::collections::HashMap;
+
+    use sea_orm::{ColumnTrait, DatabaseConnection, EntityTrait, QueryFilter, QueryOrder, TransactionTrait};
+    use sea_orm::ActiveValue::Set;
+    use uuid::Uuid;
+
+    use crate::entity::client_tag;
+    use crate::entity::prelude::DbClientTag;
+    use crate::result::HEError;
+
+    /// The tags of each of the given clients, sorted, clients without tags are left out
+    pub async fn find_tags(client_ids: &[Uuid], db: &DatabaseConnection) -> Result<HashMap<Uuid, Vec<String>>, HEError> {
+        let mut result: HashMap<Uuid, Vec<String>> = HashMap::new();
+        let tags = DbClientTag::find()
+            .filter(client_tag::Column::ClientId.is_in(client_ids.iter().copied()))
+            .order_by_asc(client_tag::Column::Tag)
+            .all(db)
+            .await?;
+        for tag in tags {
+            result.entry(tag.client_id).or_default().push(tag.tag);
+        }
+        Ok(result)
+    }
+
+    pub async fn replace_tags(client_id: &Uuid, tags: &[String], db: &DatabaseConnection) -> Result<(), HEError> {
+        let txn = db.begin().await?;
+        DbClientTag::delete_many()
+            .filter(client_tag::Column::ClientId.eq(*client_id))
+            .exec(&txn)
+            .await?;
+        if !tags.is_empty() {
+            DbClientTag::insert_many(tags.iter().map(|tag| client_tag::ActiveModel {
+                client_id: Set(*client_id),
+                tag: Set(tag.clone()),
+            }))
+                .exec(&txn)
+                .await?;
+        }
+        txn.commit().await?;
+        Ok(())
+    }
+}
+
+pub(crate) mod address_history {
+    use std::collections::HashMap;
+
+    use sea_orm::{ActiveModelTrait, ColumnTrait, DatabaseConnection, EntityTrait, PaginatorTrait, QueryFilter, QueryOrder};
+    use sea_orm::ActiveValue::Set;
+    use sea_orm::sea_query::{Alias, Expr, Query};
+    use time::{OffsetDateTime, UtcOffset};
+    use uuid::Uuid;
+
+    use public_lib::message::IpAddresses;
+
+    use crate::entity::address_history;
+    use crate::entity::prelude::DbAddressHistory;
+    use crate::result::HEError;
+
+    /// Extends the latest history entry of the client if it holds the same addresses,
+    /// otherwise starts a new entry. Returns whether the addresses changed.
+    ///
+    /// The times are stored in UTC so they compare correctly whatever the local offset was.
+    pub async fn record_addresses(client_id: &Uuid, adapter_addresses: &[IpAddresses], db: &DatabaseConnection) -> Result<bool, HEError> {
+        let mut adapter_addresses = adapter_addresses.to_vec();
+        adapter_addresses.sort_by(|a, b| a.name.cmp(&b.name));
+        let addresses = serde_json::to_value(adapter_addresses)
+            .map_err(|e| HEError::Message(format!("Error serializing addresses: {}", e)))?;
+        let now = OffsetDateTime::now_utc();
+
+        match find_latest_addresses(client_id, db).await? {
+            Some(latest) if latest.addresses == addresses => {
+                let mut latest: address_history::ActiveModel = latest.into();
+                latest.last_seen_time = Set(now);
+                latest.update(db).await?;
+                Ok(false)
+            }
+            _ => {
+                address_history::ActiveModel {
+                    client_id: Set(*client_id),
+                    addresses: Set(addresses),
+                    first_seen_time: Set(now),
+                    last_seen_time: Set(now),
+                    ..Default::default()
+                }.insert(db).await?;
+                Ok(true)
+            }
+        }
+    }
+
+    pub async fn find_latest_addresses(client_id: &Uuid, db: &DatabaseConnection) -> Result<Option<address_history::Model>, HEError> {
+        Ok(DbAddressHistory::find()
+            .filter(address_history::Column::ClientId.eq(*client_id))
+            .order_by_desc(address_history::Column::LastSeenTime)
+            .one(db)
+            .await?)
+    }
+
+    /// Finds the latest history entry of each of the clients in one query,
+    /// clients without any history are left out.
+    pub async fn find_latest_addresses_of(client_ids: &[Uuid], db: &DatabaseConnection) -> Result<HashMap<Uuid, address_history::Model>, HEError> {
+        let latest = Alias::new("latest");
+        let latest_seen_time = Query::select()
+            .expr(Expr::col((latest.clone(), address_history::Column::LastSeenTime)).max())
+            .from_as(DbAddressHistory, latest.clone())
+            .and_where(Expr::col((latest, address_history::Column::ClientId))
+                .equals((DbAddressHistory, address_history::Column::ClientId)))
+            .to_owned();
+        let entries = DbAddressHistory::find()
+            .filter(address_history::Column::ClientId.is_in(client_ids.iter().copied()))
+            .filter(Expr::col((DbAddressHistory, address_history::Column::LastSeenTime)).in_subquery(latest_seen_time))
+            .order_by_desc(address_history::Column::Id)
+            .all(db)
+            .await?;
+        let mut result = HashMap::with_capacity(entries.len());
+        for entry in entries {
+            // entries seen at the very same time, keep the newest one
+            result.entry(entry.client_id).or_insert(entry);
+        }
+        Ok(result)
+    }
+
+    /// Finds the history entries of the client overlapping the given time range, newest first,
+    /// `page` starts from 0. Returns the entries of the page and the total number of entries.
+    pub async fn find_addresses_history(
+        client_id: &Uuid,
+        from: Option<OffsetDateTime>,
+        to: Option<OffsetDateTime>,
+        page: u64,
+        page_size: u64,
+        db: &DatabaseConnection,
+    ) -> Result<(Vec<address_history::Model>, u64), HEError> {
+        let mut query = DbAddressHistory::find()
+            .filter(address_history::Column::ClientId.eq(*client_id));
+        if let Some(from) = from {
+            query = query.filter(address_history::Column::LastSeenTime.gte(from.to_offset(UtcOffset::UTC)));
+        }
+        if let Some(to) = to {
+            query = query.filter(address_history::Column::FirstSeenTime.lte(to.to_offset(UtcOffset::UTC)));
+        }
+        let paginator = query
+            .order_by_desc(address_history::Column::LastSeenTime)
+            .paginate(db, page_size);
+        let total = paginator.num_items().await?;
+        let entries = paginator.fetch_page(page).await?;
+        Ok((entries, total))
+    }
+}
+
+pub(crate) mod client_token {
+    use sea_orm::{ActiveModelTrait, ColumnTrait, Condition, DatabaseConnection, EntityTrait, QueryFilter, QueryOrder, TransactionTrait};
+    use sea_orm::ActiveValue::Set;
+    use sea_orm::prelude::Expr;
+    use time::UtcOffset;
+    use uuid::Uuid;
+
+    use public_lib::times::local_offset_date_time;
+
+    use crate::auth::hash_token;
+    use crate::db::client::save_new_client_information;
+    use crate::entity::client_token;
+    use crate::entity::prelude::DbClientToken;
+    use crate::result::HEError;
+
+    pub async fn issue_token(
+        token: &str,
+        client_id: Option<Uuid>,
+        description: Option<String>,
+        db: &DatabaseConnection,
+        default_offset: &UtcOffset,
+    ) -> Result<client_token::Model, HEError> {
+        let new_token = client_token::ActiveModel {
+            id: Set(Uuid::new_v4()),
+            client_id: Set(client_id),
+            token_hash: Set(hash_token(token)),
+            description: Set(description),
+            create_time: Set(local_offset_date_time(default_offset)),
+            last_used_time: Set(None),
+        };
+        Ok(new_token.insert(db).await?)
+    }
+
+    pub async fn find_tokens(db: &DatabaseConnection) -> Result<Vec<client_token::Model>, HEError> {
+        Ok(DbClientToken::find()
+            .order_by_asc(client_token::Column::CreateTime)
+            .all(db)
+            .await?)
+    }
+
+    /// Returns the revoked token, if there was one
+    pub async fn revoke_token(id: &Uuid, db: &DatabaseConnection) -> Result<Option<client_token::Model>, HEError> {
+        let db_token = DbClientToken::find_by_id(*id).one(db).await?;
+        if db_token.is_some() {
+            DbClientToken::delete_by_id(*id).exec(db).await?;
+        }
+        Ok(db_token)
+    }
+
+    /// Revokes all the tokens bound to the client, returns how many there were
+    pub async fn revoke_client_tokens(client_id: &Uuid, db: &DatabaseConnection) -> Result<u64, HEError> {
+        let result = DbClientToken::delete_many()
+            .filter(client_token::Column::ClientId.eq(*client_id))
+            .exec(db)
+            .await?;
+        Ok(result.rows_affected)
+    }
+
+    /// Checks the token presented by a client, a token not bound to any client yet is bound
+    /// to the first client using it.
+    pub async fn verify_token(client_id: &Uuid, token: &str, db: &DatabaseConnection, default_offset: &UtcOffset) -> Result<bool, HEError> {
+        let db_token = DbClientToken::find()
+            .filter(client_token::Column::TokenHash.eq(hash_token(token)))
+            .one(db)
+            .await?;
+        let Some(db_token) = db_token else {
+            return Ok(false);
+        };
+        if db_token.client_id.is_some_and(|bound_id| bound_id != *client_id) {
+            return Ok(false);
+        }
+        let txn = db.begin().await?;
+        if db_token.client_id.is_none() {
+            save_new_client_information(client_id, &txn, default_offset).await?;
+        }
+        // checked again while binding, as another client may have bound the token since it was read
+        let result = DbClientToken::update_many()
+            .col_expr(client_token::Column::ClientId, Expr::value(Some(*client_id)))
+            .col_expr(client_token::Column::LastUsedTime, Expr::value(Some(local_offset_date_time(default_offset))))
+            .filter(client_token::Column::Id.eq(db_token.id))
+            .filter(Condition::any()
+                .add(client_token::Column::ClientId.is_null())
+                .add(client_token::Column::ClientId.eq(*client_id)))
+            .exec(&txn)
+            .await?;
+        if result.rows_affected == 0 {
+            txn.rollback().await?;
+            return Ok(false);
+        }
+        txn.commit().await?;
+        Ok(true)
+    }
+}
+
+pub(crate) mod dns_record {
+    use std::net::IpAddr;
+
+    use sea_orm::{ActiveModelTrait, ColumnTrait, DatabaseConnection, EntityTrait, QueryFilter, QueryOrder};
+    use sea_orm::ActiveValue::Set;
+    use time::UtcOffset;
+    use uuid::Uuid;
+
+    use public_lib::times::local_offset_date_time;
+
+    use crate::entity::dns_record;
+    use crate::entity::prelude::DbDnsRecord;
+    use crate::result::HEError;
+
+    pub async fn find_records(client_id: &Uuid, db: &DatabaseConnection) -> Result<Vec<dns_record::Model>, HEError> {
+        Ok(DbDnsRecord::find()
+            .filter(dns_record::Column::ClientId.eq(*client_id))
+            .order_by_asc(dns_record::Column::Id)
+            .all(db)
+            .await?)
+    }
+
+    pub async fn create_record(client_id: &Uuid, hostname: String, adapter: String, db: &DatabaseConnection) -> Result<dns_record::Model, HEError> {
+        let existing = DbDnsRecord::find()
+            .filter(dns_record::Column::Hostname.eq(hostname.as_str()))
+            .one(db)
+            .await?;
+        if existing.is_some() {
+            return Err(HEError::Conflict(format!("hostname {} is already mapped", hostname)));
+        }
+        let new_record = dns_record::ActiveModel {
+            client_id: Set(*client_id),
+            hostname: Set(hostname),
+            adapter: Set(adapter),
+            published_addresses: Set(None),
+            last_update_time: Set(None),
+            ..Default::default()
+        };
+        Ok(new_record.insert(db).await?)
+    }
+
+    /// Returns the record, if the client has one with this id
+    pub async fn find_record(client_id: &Uuid, id: i64, db: &DatabaseConnection) -> Result<Option<dns_record::Model>, HEError> {
+        Ok(DbDnsRecord::find_by_id(id)
+            .filter(dns_record::Column::ClientId.eq(*client_id))
+            .one(db)
+            .await?)
+    }
+
+    pub async fn delete_record(id: i64, db: &DatabaseConnection) -> Result<(), HEError> {
+        DbDnsRecord::delete_by_id(id).exec(db).await?;
+        Ok(())
+    }
+
+    pub async fn mark_published(record: dns_record::Model, addresses: &[IpAddr], db: &DatabaseConnection, default_offset: &UtcOffset) -> Result<(), HEError> {
+        let addresses = serde_json::to_value(addresses)
+            .map_err(|e| HEError::Message(format!("Error serializing addresses: {}", e)))?;
+        let mut record: dns_record::ActiveModel = record.into();
+        record.published_addresses = Set(Some(addresses));
+        record.last_update_time = Set(Some(local_offset_date_time(default_offset)));
+        record.update(db).await?;
+        Ok(())
+    }
+}
+
+pub(crate) mod webhook {
+    use sea_orm::{ActiveModelTrait, ColumnTrait, DatabaseConnection, EntityTrait, PaginatorTrait, QueryFilter, QueryOrder};
+    use sea_orm::ActiveValue::Set;
+    use serde_json::Value;
+    use time::UtcOffset;
+    use uuid::Uuid;
+
+    use public_lib::times::local_offset_date_time;
+
+    use crate::entity::{webhook, webhook_delivery};
+    use crate::entity::prelude::{DbWebhook, DbWebhookDelivery};
+    use crate::events::ClientEventKind;
+    use crate::result::HEError;
+
+    pub async fn create_webhook(
+        url: String,
+        secret: Option<String>,
+        events: &[ClientEventKind],
+        description: Option<String>,
+        db: &DatabaseConnection,
+        default_offset: &UtcOffset,
+    ) -> Result<webhook::Model, HEError> {
+        let events = serde_json::to_value(events)
+            .map_err(|e| HEError::Message(format!("Error serializing events: {}", e)))?;
+        let new_webhook = webhook::ActiveModel {
+            id: Set(Uuid::new_v4()),
+            url: Set(url),
+            secret: Set(secret),
+            events: Set(events),
+            description: Set(description),
+            create_time: Set(local_offset_date_time(default_offset)),
+        };
+        Ok(new_webhook.insert(db).await?)
+    }
+
+    pub async fn find_webhooks(db: &DatabaseConnection) -> Result<Vec<webhook::Model>, HEError> {
+        Ok(DbWebhook::find()
+            .order_by_asc(webhook::Column::CreateTime)
+            .all(db)
+            .await?)
+    }
+
+    /// Returns the deleted webhook, if there was one
+    pub async fn delete_webhook(id: &Uuid, db: &DatabaseConnection) -> Result<Option<webhook::Model>, HEError> {
+        let db_webhook = DbWebhook::find_by_id(*id).one(db).await?;
+        if db_webhook.is_some() {
+            DbWebhook::delete_by_id(*id).exec(db).await?;
+        }
+        Ok(db_webhook)
+    }
+
+    /// Logs a single delivery attempt, `status_code` being absent if no response was received
+    #[allow(clippy::too_many_arguments)]
+    pub async fn record_delivery(
+        webhook_id: &Uuid,
+        event: ClientEventKind,
+        payload: Value,
+        attempt: i32,
+        status_code: Option<i32>,
+        error: Option<String>,
+        db: &DatabaseConnection,
+        default_offset: &UtcOffset,
+    ) -> Result<(), HEError> {
+        webhook_delivery::ActiveModel {
+            webhook_id: Set(*webhook_id),
+            event: Set(event.name().to_string()),
+            payload: Set(payload),
+            attempt: Set(attempt),
+            status_code: Set(status_code),
+            success: Set(error.is_none()),
+            error: Set(error),
+            delivery_time: Set(local_offset_date_time(default_offset)),
+            ..Default::default()
+        }.insert(db).await?;
+        Ok(())
+    }
+
+    /// Finds the delivery attempts of the webhook, newest first, `page` starts from 0.
+    /// Returns the attempts of the page and the total number of attempts.
+    pub async fn find_deliveries(
+        webhook_id: &Uuid,
+        page: u64,
+        page_size: u64,
+        db: &DatabaseConnection,
+    ) -> Result<(Vec<webhook_delivery::Model>, u64), HEError> {
+        let paginator = DbWebhookDelivery::find()
+            .filter(webhook_delivery::Column::WebhookId.eq(*webhook_id))
+            .order_by_desc(webhook_delivery::Column::Id)
+            .paginate(db, page_size);
+        let total = paginator.num_items().await?;
+        let entries = paginator.fetch_page(page).await?;
+        Ok((entries, total))
+    }
+}
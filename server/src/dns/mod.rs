@@ -0,0 +1,211 @@
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex as StdMutex};
+
+use async_trait::async_trait;
+use axum::extract::{Path, State};
+use axum::Json;
+use sea_orm::EntityTrait;
+use serde::Deserialize;
+use tokio::sync::{Mutex, OwnedMutexGuard};
+use tracing::{error, info};
+use uuid::Uuid;
+
+use public_lib::message::{AddressScope, IpAddresses};
+
+use crate::{AppState, db};
+use crate::entity::dns_record;
+use crate::entity::prelude::DbClient;
+use crate::result::HEError;
+
+pub mod rfc2136;
+
+/// Publishes the addresses of the clients as DNS records
+#[async_trait]
+pub trait DnsUpdater: Send + Sync {
+    /// Turns a hostname given by the user into the fully qualified name to publish,
+    /// rejecting names this updater cannot publish
+    fn qualify_name(&self, hostname: &str) -> Result<String, HEError>;
+
+    /// Replaces the A and AAAA records of the fully qualified `name` with `addresses`,
+    /// removing them if there are no addresses
+    async fn replace_addresses(&self, name: &str, addresses: &[IpAddr]) -> Result<(), HEError>;
+}
+
+/// Validates a DNS name, a name without the trailing dot is considered relative to `zone`,
+/// and must be inside of it after all if the zone is given.
+pub fn qualify_name(name: &str, zone: Option<&str>) -> Result<String, HEError> {
+    let name = name.trim().to_ascii_lowercase();
+    let qualified = match (name.strip_suffix('.'), zone) {
+        (Some(_), _) | (None, None) => name.trim_end_matches('.').to_string() + ".",
+        (None, Some(zone)) => format!("{}.{}", name, zone),
+    };
+    let labels: Vec<&str> = qualified.trim_end_matches('.').split('.').collect();
+    let valid = qualified.len() <= 254 && labels.iter().all(|label| {
+        !label.is_empty() && label.len() <= 63
+            && label.bytes().all(|byte| byte.is_ascii_alphanumeric() || byte == b'-' || byte == b'_')
+    });
+    if !valid {
+        return Err(HEError::BadRequest(format!("'{}' is not a valid DNS name", name)));
+    }
+    if let Some(zone) = zone {
+        if qualified != zone && !qualified.ends_with(&format!(".{}", zone)) {
+            return Err(HEError::BadRequest(format!("'{}' is not inside of the zone {}", qualified, zone)));
+        }
+    }
+    Ok(qualified)
+}
+
+/// The addresses of the adapter worth publishing, the ones other hosts may reach it at
+fn addresses_to_publish(adapter_addresses: &[IpAddresses], adapter: &str) -> Vec<IpAddr> {
+    adapter_addresses.iter()
+        .filter(|addresses| addresses.name == adapter)
+        .flat_map(|addresses| addresses.addresses.iter())
+        .filter(|address| matches!(address.scope, AddressScope::Global | AddressScope::Private | AddressScope::UniqueLocal))
+        .map(|address| address.ip)
+        .collect()
+}
+
+/// Updates the records mapped to the client whose published addresses differ from the reported ones
+pub async fn publish_client_records(client_id: &Uuid, adapter_addresses: &[IpAddresses], state: &AppState) -> Result<(), HEError> {
+    let Some(updater) = &state.dns_updater else {
+        return Ok(());
+    };
+    for record in db::dns_record::find_records(client_id, &state.db).await? {
+        let addresses = addresses_to_publish(adapter_addresses, &record.adapter);
+        let published: Option<Vec<IpAddr>> = record.published_addresses.clone()
+            .and_then(|published| serde_json::from_value(published).ok());
+        if published.as_ref() == Some(&addresses) {
+            continue;
+        }
+        match updater.replace_addresses(&record.hostname, &addresses).await {
+            Ok(()) => {
+                info!("Published {:?} as {} for client {}", &addresses, &record.hostname, client_id);
+                db::dns_record::mark_published(record, &addresses, &state.db, &state.default_offset).await?;
+            }
+            Err(e) => error!("Failed to publish {} for client {}: {}", &record.hostname, client_id, e),
+        }
+    }
+    Ok(())
+}
+
+/// Removes the records published for the client from DNS, before the client is deleted
+pub async fn unpublish_client_records(client_id: &Uuid, state: &AppState) -> Result<(), HEError> {
+    let Some(updater) = &state.dns_updater else {
+        return Ok(());
+    };
+    let _guard = state.dns_publishes.lock(client_id).await;
+    for record in db::dns_record::find_records(client_id, &state.db).await? {
+        if record.published_addresses.is_none() {
+            continue;
+        }
+        if let Err(e) = updater.replace_addresses(&record.hostname, &[]).await {
+            error!("Failed to remove {} of client {} from DNS: {}", &record.hostname, client_id, e);
+        }
+    }
+    Ok(())
+}
+
+/// Serializes the DNS updates of each client, so that the updates of older addresses cannot
+/// reach the nameserver after newer ones
+#[derive(Default)]
+pub struct DnsPublishQueue {
+    clients: StdMutex<HashMap<Uuid, Arc<ClientPublishes>>>,
+}
+
+#[derive(Default)]
+struct ClientPublishes {
+    /// Latest addresses not published yet, taken by whichever task gets the lock first
+    pending: StdMutex<Option<Vec<IpAddresses>>>,
+    lock: Arc<Mutex<()>>,
+}
+
+impl DnsPublishQueue {
+    fn client(&self, client_id: &Uuid) -> Arc<ClientPublishes> {
+        self.clients.lock().unwrap().entry(*client_id).or_default().clone()
+    }
+
+    /// Holds off the publishes of the client, while its records are being removed
+    pub async fn lock(&self, client_id: &Uuid) -> OwnedMutexGuard<()> {
+        self.client(client_id).lock.clone().lock_owned().await
+    }
+
+    /// Forgets a deleted client
+    pub fn remove(&self, client_id: &Uuid) {
+        self.clients.lock().unwrap().remove(client_id);
+    }
+}
+
+/// Publishes the records in the background, so that a slow nameserver does not hold up the client.
+/// Addresses reported while a publish is running replace each other, only the latest are published next.
+pub fn spawn_publish_client_records(client_id: Uuid, adapter_addresses: Vec<IpAddresses>, state: AppState) {
+    if state.dns_updater.is_none() {
+        return;
+    }
+    let publishes = state.dns_publishes.client(&client_id);
+    *publishes.pending.lock().unwrap() = Some(adapter_addresses);
+    tokio::spawn(async move {
+        let _guard = publishes.lock.lock().await;
+        let Some(adapter_addresses) = publishes.pending.lock().unwrap().take() else {
+            return;
+        };
+        if let Err(e) = publish_client_records(&client_id, &adapter_addresses, &state).await {
+            error!("Failed to publish DNS records of client {}: {:?}", &client_id, e);
+        }
+    });
+}
+
+pub async fn get_dns_records(
+    State(state): State<AppState>,
+    Path(id): Path<Uuid>,
+) -> Result<Json<Vec<dns_record::Model>>, HEError> {
+    if DbClient::find_by_id(id).one(&state.db).await?.is_none() {
+        return Err(HEError::NotFound(format!("client {} not found", id)));
+    }
+    Ok(Json(db::dns_record::find_records(&id, &state.db).await?))
+}
+
+#[derive(Deserialize)]
+pub struct CreateDnsRecordBody {
+    /// Relative to the configured zone, unless ending with a dot
+    hostname: String,
+    /// Name of the client adapter whose addresses are published
+    adapter: String,
+}
+
+pub async fn create_dns_record(
+    State(state): State<AppState>,
+    Path(id): Path<Uuid>,
+    Json(body): Json<CreateDnsRecordBody>,
+) -> Result<Json<dns_record::Model>, HEError> {
+    let Some(updater) = &state.dns_updater else {
+        return Err(HEError::BadRequest("DNS updates are not configured on this server".to_string()));
+    };
+    if DbClient::find_by_id(id).one(&state.db).await?.is_none() {
+        return Err(HEError::NotFound(format!("client {} not found", id)));
+    }
+    let hostname = updater.qualify_name(&body.hostname)?;
+    let record = db::dns_record::create_record(&id, hostname, body.adapter, &state.db).await?;
+    if let Some(client) = state.clients.read().await.get(&id) {
+        if let Some(adapter_addresses) = client.latest_adapter_addresses().await {
+            spawn_publish_client_records(id, adapter_addresses, state.clone());
+        }
+    }
+    Ok(Json(record))
+}
+
+/// Deletes the mapping, removing the records it published from DNS
+pub async fn delete_dns_record(
+    State(state): State<AppState>,
+    Path((id, record_id)): Path<(Uuid, i64)>,
+) -> Result<(), HEError> {
+    let _guard = state.dns_publishes.lock(&id).await;
+    let Some(record) = db::dns_record::find_record(&id, record_id, &state.db).await? else {
+        return Err(HEError::NotFound(format!("DNS record {} of client {} not found", record_id, id)));
+    };
+    // the mapping is kept if the records cannot be removed, so that deleting it can be retried
+    if let (Some(updater), Some(_)) = (&state.dns_updater, &record.published_addresses) {
+        updater.replace_addresses(&record.hostname, &[]).await?;
+    }
+    db::dns_record::delete_record(record_id, &state.db).await
+}
@@ -0,0 +1,478 @@
+use std::net::IpAddr;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use async_trait::async_trait;
+use clap::ValueEnum;
+use hmac::{Hmac, Mac};
+use sha2::{Sha256, Sha512};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+use crate::dns::{DnsUpdater, qualify_name};
+use crate::result::HEError;
+
+const OPCODE_UPDATE: u16 = 5 << 11;
+const TYPE_A: u16 = 1;
+const TYPE_SOA: u16 = 6;
+const TYPE_AAAA: u16 = 28;
+const TYPE_TSIG: u16 = 250;
+const CLASS_IN: u16 = 1;
+const CLASS_ANY: u16 = 255;
+/// Allowed clock skew between us and the nameserver when checking the TSIG time
+const TSIG_FUDGE: u16 = 300;
+
+#[derive(ValueEnum, Clone, Copy, Debug, Default)]
+pub enum TsigAlgorithm {
+    #[default]
+    HmacSha256,
+    HmacSha512,
+}
+
+impl TsigAlgorithm {
+    fn name(&self) -> &'static str {
+        match self {
+            TsigAlgorithm::HmacSha256 => "hmac-sha256.",
+            TsigAlgorithm::HmacSha512 => "hmac-sha512.",
+        }
+    }
+
+    fn sign(&self, secret: &[u8], data: &[u8]) -> Vec<u8> {
+        match self {
+            TsigAlgorithm::HmacSha256 => {
+                let mut mac = Hmac::<Sha256>::new_from_slice(secret).expect("HMAC accepts keys of any length");
+                mac.update(data);
+                mac.finalize().into_bytes().to_vec()
+            }
+            TsigAlgorithm::HmacSha512 => {
+                let mut mac = Hmac::<Sha512>::new_from_slice(secret).expect("HMAC accepts keys of any length");
+                mac.update(data);
+                mac.finalize().into_bytes().to_vec()
+            }
+        }
+    }
+
+    /// Only accepts full length MACs, compared in constant time
+    fn verify(&self, secret: &[u8], data: &[u8], expected: &[u8]) -> bool {
+        match self {
+            TsigAlgorithm::HmacSha256 => {
+                let mut mac = Hmac::<Sha256>::new_from_slice(secret).expect("HMAC accepts keys of any length");
+                mac.update(data);
+                mac.verify_slice(expected).is_ok()
+            }
+            TsigAlgorithm::HmacSha512 => {
+                let mut mac = Hmac::<Sha512>::new_from_slice(secret).expect("HMAC accepts keys of any length");
+                mac.update(data);
+                mac.verify_slice(expected).is_ok()
+            }
+        }
+    }
+}
+
+/// TSIG record ending a message
+#[derive(Debug)]
+struct Tsig {
+    /// Offset of the record in the message
+    start: usize,
+    key_name: String,
+    algorithm: String,
+    time_signed: u64,
+    fudge: u16,
+    mac: Vec<u8>,
+    original_id: u16,
+    error: u16,
+    other_data: Vec<u8>,
+}
+
+pub struct TsigKey {
+    pub name: String,
+    pub algorithm: TsigAlgorithm,
+    pub secret: Vec<u8>,
+}
+
+/// Publishes the records through RFC 2136 dynamic updates signed with a TSIG key (RFC 8945),
+/// sent over TCP to the primary nameserver of the zone.
+pub struct Rfc2136Updater {
+    server: String,
+    zone: String,
+    ttl: u32,
+    key: TsigKey,
+    timeout: Duration,
+}
+
+impl Rfc2136Updater {
+    pub fn new(server: String, zone: &str, ttl: u32, key: TsigKey, timeout: Duration) -> Result<Rfc2136Updater, HEError> {
+        let zone = qualify_name(zone, None)?;
+        let key = TsigKey { name: qualify_name(&key.name, None)?, ..key };
+        Ok(Rfc2136Updater { server, zone, ttl, key, timeout })
+    }
+
+    fn build_update(&self, id: u16, name: &str, addresses: &[IpAddr]) -> Vec<u8> {
+        let mut message = Vec::with_capacity(512);
+        put_u16(&mut message, id);
+        put_u16(&mut message, OPCODE_UPDATE);
+        // one zone, no prerequisites, the updates, no additional records until signed
+        put_u16(&mut message, 1);
+        put_u16(&mut message, 0);
+        put_u16(&mut message, 2 + addresses.len() as u16);
+        put_u16(&mut message, 0);
+
+        put_name(&mut message, &self.zone);
+        put_u16(&mut message, TYPE_SOA);
+        put_u16(&mut message, CLASS_IN);
+
+        // deleting both RRsets first turns the additions below into a replacement
+        for record_type in [TYPE_A, TYPE_AAAA] {
+            put_name(&mut message, name);
+            put_u16(&mut message, record_type);
+            put_u16(&mut message, CLASS_ANY);
+            message.extend_from_slice(&0u32.to_be_bytes());
+            put_u16(&mut message, 0);
+        }
+        for address in addresses {
+            let (record_type, data) = match address {
+                IpAddr::V4(v4) => (TYPE_A, v4.octets().to_vec()),
+                IpAddr::V6(v6) => (TYPE_AAAA, v6.octets().to_vec()),
+            };
+            put_name(&mut message, name);
+            put_u16(&mut message, record_type);
+            put_u16(&mut message, CLASS_IN);
+            message.extend_from_slice(&self.ttl.to_be_bytes());
+            put_u16(&mut message, data.len() as u16);
+            message.extend_from_slice(&data);
+        }
+        message
+    }
+
+    /// Appends the TSIG record to the message and returns its MAC, which covers the MAC of the
+    /// request being answered if any, the message as it was before, and the TSIG variables
+    fn sign(&self, message: &mut Vec<u8>, id: u16, request_mac: Option<&[u8]>) -> Vec<u8> {
+        let time_signed = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+        let time_signed = &time_signed.to_be_bytes()[2..];
+
+        let mut signed_data = Vec::with_capacity(message.len() + 128);
+        if let Some(request_mac) = request_mac {
+            put_u16(&mut signed_data, request_mac.len() as u16);
+            signed_data.extend_from_slice(request_mac);
+        }
+        signed_data.extend_from_slice(message);
+        self.put_tsig_variables(&mut signed_data, time_signed, TSIG_FUDGE, 0, &[]);
+        let mac = self.key.algorithm.sign(&self.key.secret, &signed_data);
+
+        let mut data = Vec::new();
+        put_name(&mut data, self.key.algorithm.name());
+        data.extend_from_slice(time_signed);
+        put_u16(&mut data, TSIG_FUDGE);
+        put_u16(&mut data, mac.len() as u16);
+        data.extend_from_slice(&mac);
+        put_u16(&mut data, id);
+        put_u16(&mut data, 0);
+        put_u16(&mut data, 0);
+
+        put_name(message, &self.key.name);
+        put_u16(message, TYPE_TSIG);
+        put_u16(message, CLASS_ANY);
+        message.extend_from_slice(&0u32.to_be_bytes());
+        put_u16(message, data.len() as u16);
+        message.extend_from_slice(&data);
+        let additional_count = u16::from_be_bytes([message[10], message[11]]) + 1;
+        message[10..12].copy_from_slice(&additional_count.to_be_bytes());
+        mac
+    }
+
+    fn put_tsig_variables(&self, buffer: &mut Vec<u8>, time_signed: &[u8], fudge: u16, error: u16, other_data: &[u8]) {
+        put_name(buffer, &self.key.name);
+        put_u16(buffer, CLASS_ANY);
+        buffer.extend_from_slice(&0u32.to_be_bytes());
+        put_name(buffer, self.key.algorithm.name());
+        buffer.extend_from_slice(time_signed);
+        put_u16(buffer, fudge);
+        put_u16(buffer, error);
+        put_u16(buffer, other_data.len() as u16);
+        buffer.extend_from_slice(other_data);
+    }
+
+    /// Checks the TSIG of the response as required by RFC 8945 section 5.3, so that a forged
+    /// answer cannot make us believe an update was applied
+    fn verify_response(&self, response: &[u8], request_mac: &[u8]) -> Result<(), HEError> {
+        let tsig = parse_tsig(response)
+            .ok_or_else(|| HEError::Dns(format!("response from nameserver {} is not signed", &self.server)))?;
+        if tsig.key_name != self.key.name || tsig.algorithm != self.key.algorithm.name() {
+            return Err(HEError::Dns(format!("response from nameserver {} is signed with another key", &self.server)));
+        }
+        if tsig.error != 0 {
+            return Err(HEError::Dns(format!("nameserver {} rejected the TSIG of the update: {}", &self.server, tsig_error_name(tsig.error))));
+        }
+        let mut signed_data = Vec::with_capacity(response.len() + request_mac.len() + 64);
+        put_u16(&mut signed_data, request_mac.len() as u16);
+        signed_data.extend_from_slice(request_mac);
+        signed_data.extend_from_slice(&response[..tsig.start]);
+        // the MAC was computed before the TSIG was added, with the original id
+        signed_data[2 + request_mac.len()..][..2].copy_from_slice(&tsig.original_id.to_be_bytes());
+        let additional_count = u16::from_be_bytes([response[10], response[11]]) - 1;
+        signed_data[2 + request_mac.len() + 10..][..2].copy_from_slice(&additional_count.to_be_bytes());
+        self.put_tsig_variables(&mut signed_data, &tsig.time_signed.to_be_bytes()[2..], tsig.fudge, tsig.error, &tsig.other_data);
+        if !self.key.algorithm.verify(&self.key.secret, &signed_data, &tsig.mac) {
+            return Err(HEError::Dns(format!("response from nameserver {} has an invalid TSIG", &self.server)));
+        }
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+        if now.abs_diff(tsig.time_signed) > tsig.fudge as u64 {
+            return Err(HEError::Dns(format!("response from nameserver {} was signed too far from our time", &self.server)));
+        }
+        Ok(())
+    }
+
+    async fn exchange(&self, message: &[u8]) -> Result<Vec<u8>, HEError> {
+        let mut stream = TcpStream::connect(&self.server).await?;
+        let mut request = Vec::with_capacity(message.len() + 2);
+        put_u16(&mut request, message.len() as u16);
+        request.extend_from_slice(message);
+        stream.write_all(&request).await?;
+        let length = stream.read_u16().await?;
+        let mut response = vec![0u8; length as usize];
+        stream.read_exact(&mut response).await?;
+        Ok(response)
+    }
+}
+
+#[async_trait]
+impl DnsUpdater for Rfc2136Updater {
+    fn qualify_name(&self, hostname: &str) -> Result<String, HEError> {
+        qualify_name(hostname, Some(&self.zone))
+    }
+
+    async fn replace_addresses(&self, name: &str, addresses: &[IpAddr]) -> Result<(), HEError> {
+        let id = rand::random::<u16>();
+        let mut message = self.build_update(id, name, addresses);
+        let request_mac = self.sign(&mut message, id, None);
+        let response = tokio::time::timeout(self.timeout, self.exchange(&message)).await
+            .map_err(|_| HEError::Dns(format!("nameserver {} did not answer in time", &self.server)))??;
+        if response.len() < 12 || response[0..2] != id.to_be_bytes() {
+            return Err(HEError::Dns(format!("malformed response from nameserver {}", &self.server)));
+        }
+        self.verify_response(&response, &request_mac)?;
+        match response[3] & 0x0f {
+            0 => Ok(()),
+            rcode => Err(HEError::Dns(format!("nameserver {} refused the update of {}: {}", &self.server, name, rcode_name(rcode)))),
+        }
+    }
+}
+
+fn rcode_name(rcode: u8) -> String {
+    match rcode {
+        1 => "FORMERR".to_string(),
+        2 => "SERVFAIL".to_string(),
+        3 => "NXDOMAIN".to_string(),
+        4 => "NOTIMP".to_string(),
+        5 => "REFUSED".to_string(),
+        6 => "YXDOMAIN".to_string(),
+        7 => "YXRRSET".to_string(),
+        8 => "NXRRSET".to_string(),
+        9 => "NOTAUTH".to_string(),
+        10 => "NOTZONE".to_string(),
+        rcode => format!("RCODE {}", rcode),
+    }
+}
+
+fn tsig_error_name(error: u16) -> String {
+    match error {
+        16 => "BADSIG".to_string(),
+        17 => "BADKEY".to_string(),
+        18 => "BADTIME".to_string(),
+        22 => "BADTRUNC".to_string(),
+        error => format!("error {}", error),
+    }
+}
+
+/// Finds the TSIG record, which has to be the last additional record of the message
+fn parse_tsig(message: &[u8]) -> Option<Tsig> {
+    let count = |index: usize| u16::from_be_bytes([message[4 + 2 * index], message[5 + 2 * index]]) as usize;
+    if message.len() < 12 || count(3) == 0 {
+        return None;
+    }
+    let mut offset = 12;
+    for _ in 0..count(0) {
+        offset = read_name(message, offset)?.1 + 4;
+    }
+    for _ in 0..count(1) + count(2) + count(3) - 1 {
+        offset = read_name(message, offset)?.1 + 8;
+        offset += read_u16(message, offset)? as usize + 2;
+    }
+    let start = offset;
+    let (key_name, offset) = read_name(message, start)?;
+    if read_u16(message, offset)? != TYPE_TSIG || read_u16(message, offset + 2)? != CLASS_ANY {
+        return None;
+    }
+    let data_end = offset + 10 + read_u16(message, offset + 8)? as usize;
+    if data_end != message.len() {
+        return None;
+    }
+    let (algorithm, offset) = read_name(message, offset + 10)?;
+    let time_signed = message.get(offset..offset + 6)?.iter().fold(0u64, |time, byte| time << 8 | *byte as u64);
+    let fudge = read_u16(message, offset + 6)?;
+    let mac_end = offset + 10 + read_u16(message, offset + 8)? as usize;
+    let mac = message.get(offset + 10..mac_end)?.to_vec();
+    let original_id = read_u16(message, mac_end)?;
+    let error = read_u16(message, mac_end + 2)?;
+    let other_end = mac_end + 6 + read_u16(message, mac_end + 4)? as usize;
+    if other_end != data_end {
+        return None;
+    }
+    let other_data = message.get(mac_end + 6..other_end)?.to_vec();
+    Some(Tsig { start, key_name, algorithm, time_signed, fudge, mac, original_id, error, other_data })
+}
+
+fn read_u16(message: &[u8], offset: usize) -> Option<u16> {
+    message.get(offset..offset + 2).map(|bytes| u16::from_be_bytes([bytes[0], bytes[1]]))
+}
+
+/// Reads a possibly compressed name as a lowercase fully qualified name, with the offset
+/// following it in the message
+fn read_name(message: &[u8], mut offset: usize) -> Option<(String, usize)> {
+    let mut name = String::new();
+    let mut next = None;
+    // every pointer has to go backwards, so that a malicious message cannot loop forever
+    let mut limit = offset;
+    loop {
+        let length = *message.get(offset)? as usize;
+        match length {
+            0 => break,
+            length if length & 0xc0 == 0xc0 => {
+                let pointer = (read_u16(message, offset)? & 0x3fff) as usize;
+                if pointer >= limit {
+                    return None;
+                }
+                next.get_or_insert(offset + 2);
+                limit = pointer;
+                offset = pointer;
+            }
+            length if length <= 63 => {
+                let label = message.get(offset + 1..offset + 1 + length)?;
+                name.push_str(&String::from_utf8_lossy(label).to_ascii_lowercase());
+                name.push('.');
+                offset += 1 + length;
+            }
+            _ => return None,
+        }
+    }
+    if name.is_empty() {
+        name.push('.');
+    }
+    Some((name, next.unwrap_or(offset + 1)))
+}
+
+fn put_u16(buffer: &mut Vec<u8>, value: u16) {
+    buffer.extend_from_slice(&value.to_be_bytes());
+}
+
+/// Writes a fully qualified name in the uncompressed, lowercase wire format
+fn put_name(buffer: &mut Vec<u8>, name: &str) {
+    for label in name.trim_end_matches('.').split('.').filter(|label| !label.is_empty()) {
+        buffer.push(label.len() as u8);
+        buffer.extend(label.bytes().map(|byte| byte.to_ascii_lowercase()));
+    }
+    buffer.push(0);
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::IpAddr;
+    use std::time::Duration;
+
+    use super::*;
+
+    fn updater(secret: &[u8]) -> Rfc2136Updater {
+        let key = TsigKey { name: "Update-Key".to_string(), algorithm: TsigAlgorithm::HmacSha256, secret: secret.to_vec() };
+        Rfc2136Updater::new("127.0.0.1:53".to_string(), "example.com", 300, key, Duration::from_secs(1)).unwrap()
+    }
+
+    fn signed_request(updater: &Rfc2136Updater, id: u16) -> (Vec<u8>, Vec<u8>) {
+        let addresses: [IpAddr; 2] = ["192.0.2.1".parse().unwrap(), "2001:db8::1".parse().unwrap()];
+        let mut message = updater.build_update(id, "host.example.com.", &addresses);
+        let mac = updater.sign(&mut message, id, None);
+        (message, mac)
+    }
+
+    /// Answer to the update, echoing its zone section like nameservers do
+    fn response(id: u16) -> Vec<u8> {
+        let mut response = Vec::new();
+        put_u16(&mut response, id);
+        put_u16(&mut response, 0x8000 | OPCODE_UPDATE);
+        for count in [1, 0, 0, 0] {
+            put_u16(&mut response, count);
+        }
+        put_name(&mut response, "example.com.");
+        put_u16(&mut response, TYPE_SOA);
+        put_u16(&mut response, CLASS_IN);
+        response
+    }
+
+    #[test]
+    fn request_tsig_is_encoded() {
+        let updater = updater(b"secret");
+        let (message, mac) = signed_request(&updater, 0x1234);
+        assert_eq!(u16::from_be_bytes([message[10], message[11]]), 1);
+        let tsig = parse_tsig(&message).unwrap();
+        assert_eq!(tsig.key_name, "update-key.");
+        assert_eq!(tsig.algorithm, "hmac-sha256.");
+        assert_eq!(tsig.fudge, TSIG_FUDGE);
+        assert_eq!(tsig.original_id, 0x1234);
+        assert_eq!(tsig.error, 0);
+        assert!(tsig.other_data.is_empty());
+        assert_eq!(tsig.mac, mac);
+        assert_eq!(mac.len(), 32);
+
+        // the MAC covers the unsigned message followed by the TSIG variables
+        let mut signed_data = message[..tsig.start].to_vec();
+        signed_data[10..12].copy_from_slice(&0u16.to_be_bytes());
+        updater.put_tsig_variables(&mut signed_data, &tsig.time_signed.to_be_bytes()[2..], tsig.fudge, 0, &[]);
+        assert!(TsigAlgorithm::HmacSha256.verify(b"secret", &signed_data, &mac));
+    }
+
+    #[test]
+    fn signed_response_is_accepted() {
+        let updater = updater(b"secret");
+        let (_, request_mac) = signed_request(&updater, 7);
+        let mut response = response(7);
+        updater.sign(&mut response, 7, Some(&request_mac));
+        updater.verify_response(&response, &request_mac).unwrap();
+    }
+
+    #[test]
+    fn response_must_be_chained_to_the_request() {
+        let updater = updater(b"secret");
+        let (_, request_mac) = signed_request(&updater, 7);
+        let (_, other_request_mac) = signed_request(&updater, 8);
+        let mut response = response(7);
+        updater.sign(&mut response, 7, Some(&other_request_mac));
+        assert!(updater.verify_response(&response, &request_mac).is_err());
+        let mut response = self::response(7);
+        updater.sign(&mut response, 7, None);
+        assert!(updater.verify_response(&response, &request_mac).is_err());
+    }
+
+    #[test]
+    fn tampered_or_unsigned_responses_are_rejected() {
+        let updater = updater(b"secret");
+        let (_, request_mac) = signed_request(&updater, 7);
+        let mut tampered = response(7);
+        updater.sign(&mut tampered, 7, Some(&request_mac));
+        tampered[3] |= 5;
+        assert!(updater.verify_response(&tampered, &request_mac).is_err());
+        assert!(updater.verify_response(&response(7), &request_mac).is_err());
+
+        let mut other_key = response(7);
+        self::updater(b"other secret").sign(&mut other_key, 7, Some(&request_mac));
+        assert!(updater.verify_response(&other_key, &request_mac).is_err());
+    }
+
+    #[test]
+    fn compressed_names_are_read() {
+        let mut message = vec![0u8; 12];
+        put_name(&mut message, "Example.com.");
+        message.extend_from_slice(&[4, b'h', b'o', b's', b't', 0xc0, 12]);
+        assert_eq!(read_name(&message, 12), Some(("example.com.".to_string(), 25)));
+        assert_eq!(read_name(&message, 25), Some(("host.example.com.".to_string(), 32)));
+        // pointers not going backwards could loop
+        assert_eq!(read_name(&[0xc0, 0], 0), None);
+    }
+}
@@ -12,9 +12,57 @@ pub struct Model {
     pub name: String,
     pub create_time: OffsetDateTime,
     pub last_fetch_time: OffsetDateTime,
+    pub hostname: Option<String>,
+    pub os_version: Option<String>,
+    pub kernel_version: Option<String>,
+    pub arch: Option<String>,
+    pub client_version: Option<String>,
+    pub boot_time: Option<OffsetDateTime>,
+    pub peer_address: Option<String>,
+    pub public_addresses: Option<Json>,
+    pub last_seen_time: Option<OffsetDateTime>,
+    pub archived: bool,
+    pub group: Option<String>,
+    #[sea_orm(column_type = "Text", nullable)]
+    pub notes: Option<String>,
+    pub name_set_by_admin: bool,
+    pub tags_set_by_admin: bool,
 }
 
 #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
-pub enum Relation {}
+pub enum Relation {
+    #[sea_orm(has_many = "super::address_history::Entity")]
+    AddressHistory,
+    #[sea_orm(has_many = "super::client_tag::Entity")]
+    ClientTag,
+    #[sea_orm(has_many = "super::client_token::Entity")]
+    ClientToken,
+    #[sea_orm(has_many = "super::dns_record::Entity")]
+    DnsRecord,
+}
+
+impl Related<super::address_history::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::AddressHistory.def()
+    }
+}
+
+impl Related<super::client_tag::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::ClientTag.def()
+    }
+}
+
+impl Related<super::client_token::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::ClientToken.def()
+    }
+}
+
+impl Related<super::dns_record::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::DnsRecord.def()
+    }
+}
 
 impl ActiveModelBehavior for ActiveModel {}
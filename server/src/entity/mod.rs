@@ -2,4 +2,10 @@
 
 pub mod prelude;
 
+pub mod address_history;
 pub mod client;
+pub mod client_tag;
+pub mod client_token;
+pub mod dns_record;
+pub mod webhook;
+pub mod webhook_delivery;
@@ -1,3 +1,9 @@
 //! `SeaORM` Entity. Generated by sea-orm-codegen 0.12.14
 
+pub use super::address_history::Entity as DbAddressHistory;
 pub use super::client::Entity as DbClient;
+pub use super::client_tag::Entity as DbClientTag;
+pub use super::client_token::Entity as DbClientToken;
+pub use super::dns_record::Entity as DbDnsRecord;
+pub use super::webhook::Entity as DbWebhook;
+pub use super::webhook_delivery::Entity as DbWebhookDelivery;
@@ -0,0 +1,96 @@
+use std::convert::Infallible;
+use std::net::IpAddr;
+
+use axum::extract::State;
+use axum::response::sse::{Event, KeepAlive, Sse};
+use futures_util::{Stream, StreamExt};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use time::OffsetDateTime;
+use uuid::Uuid;
+
+use tokio_stream::wrappers::BroadcastStream;
+use tokio_stream::wrappers::errors::BroadcastStreamRecvError;
+use tracing::error;
+
+use public_lib::message::IpAddresses;
+
+use crate::AppState;
+
+/// Something that happened to a client, broadcast to everyone interested, e.g. the webhooks
+/// and the subscribers of `/api/events`
+#[derive(Serialize, Clone, Debug)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum ClientEvent {
+    Connected {
+        client_id: Uuid,
+        peer_address: IpAddr,
+        time: OffsetDateTime,
+    },
+    Disconnected {
+        client_id: Uuid,
+        time: OffsetDateTime,
+    },
+    /// The client reported a set of addresses different from the last one
+    AddressChanged {
+        client_id: Uuid,
+        adapter_addresses: Vec<IpAddresses>,
+        time: OffsetDateTime,
+    },
+    Renamed {
+        client_id: Uuid,
+        name: String,
+        time: OffsetDateTime,
+    },
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ClientEventKind {
+    Connected,
+    Disconnected,
+    AddressChanged,
+    Renamed,
+}
+
+impl ClientEvent {
+    pub fn kind(&self) -> ClientEventKind {
+        match self {
+            ClientEvent::Connected { .. } => ClientEventKind::Connected,
+            ClientEvent::Disconnected { .. } => ClientEventKind::Disconnected,
+            ClientEvent::AddressChanged { .. } => ClientEventKind::AddressChanged,
+            ClientEvent::Renamed { .. } => ClientEventKind::Renamed,
+        }
+    }
+}
+
+impl ClientEventKind {
+    pub fn name(&self) -> &'static str {
+        match self {
+            ClientEventKind::Connected => "connected",
+            ClientEventKind::Disconnected => "disconnected",
+            ClientEventKind::AddressChanged => "address_changed",
+            ClientEventKind::Renamed => "renamed",
+        }
+    }
+}
+
+/// Streams the client events as they happen, the name of each SSE event being the kind of the
+/// client event. A subscriber falling too far behind gets a `lagged` event instead of the events
+/// it missed, and should fetch the whole state again.
+pub async fn stream_events(State(state): State<AppState>) -> Sse<impl Stream<Item = Result<Event, Infallible>>> {
+    let stream = BroadcastStream::new(state.events.subscribe()).filter_map(|event| async move {
+        let sse_event = match event {
+            Ok(event) => Event::default().event(event.kind().name()).json_data(&event),
+            Err(BroadcastStreamRecvError::Lagged(skipped)) => Event::default().event("lagged").json_data(json!({ "skipped": skipped })),
+        };
+        match sse_event {
+            Ok(sse_event) => Some(Ok(sse_event)),
+            Err(e) => {
+                error!("Error serializing event: {}", e);
+                None
+            }
+        }
+    });
+    Sse::new(stream).keep_alive(KeepAlive::default())
+}
@@ -1,19 +1,34 @@
+use std::io::{BufRead, IsTerminal};
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+
 use axum::{middleware, Router};
-use axum::routing::{get, put};
+use axum::routing::{delete, get, put};
 use axum_embed::{FallbackBehavior, ServeEmbed};
 use base64::Engine;
 use base64::prelude::BASE64_STANDARD;
-use clap::Parser;
+use clap::{ArgGroup, Parser, Subcommand};
+use ipnet::IpNet;
 use rust_embed::RustEmbed;
 use sea_orm::DatabaseConnection;
+use sea_orm_migration::MigratorTrait;
 use time::UtcOffset;
-use tracing::warn;
+use tokio::sync::broadcast;
+use tracing::{info, warn};
 
 use clients::Clients;
+use public_lib::config::{parse_args_with_config_file, read_secret_file};
 use public_lib::tracing::{tracing_timer, TracingLogLevel};
 
-use crate::auth::basic_auth;
-use crate::db::setup_db_connection;
+use crate::auth::{AuthFailureLimiter, basic_auth, PasswordVerifier};
+use crate::db::{connect_db, setup_db_connection};
+use crate::dns::{DnsPublishQueue, DnsUpdater};
+use crate::dns::rfc2136::{Rfc2136Updater, TsigAlgorithm, TsigKey};
+use crate::events::ClientEvent;
+use crate::metrics::Metrics;
+use crate::migration::Migrator;
 
 mod result;
 mod clients;
@@ -21,6 +36,13 @@ mod db;
 mod entity;
 mod auth;
 mod migration;
+mod tokens;
+mod tls;
+mod peer;
+mod dns;
+mod events;
+mod webhooks;
+mod metrics;
 
 
 #[derive(RustEmbed, Clone)]
@@ -30,72 +52,297 @@ struct AppWebPages;
 #[derive(Parser, Debug)]
 #[command(name = "Host Exposer Server")]
 #[command(author, version, about)]
+#[command(group(ArgGroup::new("password").args(["pwd", "pwd_file", "pwd_hash", "pwd_hash_file"])))]
 struct Args {
+    #[command(subcommand)]
+    command: Option<Command>,
+    /// TOML configuration file with any of the options below, overridden by environment variables and arguments
+    #[arg(long, global = true, env = "HOST_EXPOSER_SERVER_CONFIG", value_name = "FILE")]
+    config: Option<PathBuf>,
+    /// Database to store the clients in, any SeaORM supported URL of the backends enabled at build time
+    #[arg(long, global = true, env = "HOST_EXPOSER_SERVER_DATABASE_URL", default_value = "sqlite://data.sqlite?mode=rwc", value_name = "URL")]
+    database_url: String,
     /// Port to listen on
-    #[arg(short, long, default_value = "3030")]
+    #[arg(short, long, env = "HOST_EXPOSER_SERVER_PORT", default_value = "3030")]
     port: u16,
-    /// Password for the server, if not specified, a random password of random-password-length will be generated
-    #[arg(long, value_name = "PASSWORD")]
+    /// Password for the web UI and the REST API, if not specified, a random password of random-password-length will be generated
+    #[arg(long, env = "HOST_EXPOSER_SERVER_PWD", value_name = "PASSWORD")]
     pwd: Option<String>,
+    /// File containing the password
+    #[arg(long, env = "HOST_EXPOSER_SERVER_PWD_FILE", value_name = "FILE")]
+    pwd_file: Option<PathBuf>,
+    /// Argon2 hash of the password, as printed by the hash-password subcommand
+    #[arg(long, env = "HOST_EXPOSER_SERVER_PWD_HASH", value_name = "PASSWORD_HASH")]
+    pwd_hash: Option<String>,
+    /// File containing the Argon2 hash of the password
+    #[arg(long, env = "HOST_EXPOSER_SERVER_PWD_HASH_FILE", value_name = "FILE")]
+    pwd_hash_file: Option<PathBuf>,
     /// Length of the random password to generate
-    #[arg(short, long, default_value = "16")]
+    #[arg(short, long, env = "HOST_EXPOSER_SERVER_RANDOM_PASSWORD_LENGTH", default_value = "16")]
     random_password_length: u8,
     /// Maximum Log level
-    #[arg(long, ignore_case = true, value_enum, default_value_t)]
+    #[arg(long, env = "HOST_EXPOSER_SERVER_MAX_LOG_LEVEL", ignore_case = true, value_enum, default_value_t)]
     max_log_level: TracingLogLevel,
     /// Default UTC offset if the application cannot determine the local time zone
-    #[arg(long, default_value = "+00:00", value_parser = public_lib::times::parse_utc_offset, value_name = "UTC_OFFSET")]
+    #[arg(long, env = "HOST_EXPOSER_SERVER_DEFAULT_OFFSET", default_value = "+00:00", value_parser = public_lib::times::parse_utc_offset, value_name = "UTC_OFFSET")]
     default_offset: UtcOffset,
+    /// Maximum time to wait for each client when refreshing their addresses on demand
+    #[arg(long, env = "HOST_EXPOSER_SERVER_REFRESH_TIMEOUT", default_value = "5", value_parser = clap::value_parser!(u64).range(1..), value_name = "SECONDS")]
+    refresh_timeout: u64,
+    /// Interval of the websocket pings sent to every client
+    #[arg(long, env = "HOST_EXPOSER_SERVER_HEARTBEAT_INTERVAL", default_value = "30", value_parser = clap::value_parser!(u64).range(1..), value_name = "SECONDS")]
+    heartbeat_interval: u64,
+    /// Clients which sent nothing, not even a pong, for this long are disconnected
+    #[arg(long, env = "HOST_EXPOSER_SERVER_HEARTBEAT_TIMEOUT", default_value = "90", value_parser = clap::value_parser!(u64).range(1..), value_name = "SECONDS")]
+    heartbeat_timeout: u64,
+    /// Clients which did not complete the handshake within this time after connecting are disconnected
+    #[arg(long, env = "HOST_EXPOSER_SERVER_HANDSHAKE_TIMEOUT", default_value = "10", value_parser = clap::value_parser!(u64).range(1..), value_name = "SECONDS")]
+    handshake_timeout: u64,
+    /// Ban the addresses failing to authenticate this many times within --auth-failure-window, never if 0
+    #[arg(long, env = "HOST_EXPOSER_SERVER_AUTH_MAX_FAILURES", default_value = "5", value_name = "FAILURES")]
+    auth_max_failures: u32,
+    /// Window in which the failed authentications of an address are counted
+    #[arg(long, env = "HOST_EXPOSER_SERVER_AUTH_FAILURE_WINDOW", default_value = "60", value_name = "SECONDS")]
+    auth_failure_window: u64,
+    /// How long the addresses failing to authenticate too often are banned for
+    #[arg(long, env = "HOST_EXPOSER_SERVER_AUTH_BAN_DURATION", default_value = "600", value_name = "SECONDS")]
+    auth_ban_duration: u64,
+    /// Also accept clients authenticating with the server password instead of an enrollment token,
+    /// a deprecated compatibility mode for the clients from before the tokens
+    #[arg(long, env = "HOST_EXPOSER_SERVER_ALLOW_PASSWORD_CLIENTS")]
+    allow_password_clients: bool,
+    /// PEM certificate chain to serve HTTPS and wss:// with, requires --tls-key
+    #[arg(long, env = "HOST_EXPOSER_SERVER_TLS_CERT", value_name = "FILE", requires = "tls_key")]
+    tls_cert: Option<PathBuf>,
+    /// PEM private key of the TLS certificate, requires --tls-cert
+    #[arg(long, env = "HOST_EXPOSER_SERVER_TLS_KEY", value_name = "FILE", requires = "tls_cert")]
+    tls_key: Option<PathBuf>,
+    /// Interval of checking the TLS certificate and key files for changes to reload them
+    #[arg(long, env = "HOST_EXPOSER_SERVER_TLS_RELOAD_INTERVAL", default_value = "60", value_parser = clap::value_parser!(u64).range(1..), value_name = "SECONDS")]
+    tls_reload_interval: u64,
+    /// Reverse proxies whose X-Forwarded-For header is trusted to tell the address of the clients
+    #[arg(long, env = "HOST_EXPOSER_SERVER_TRUSTED_PROXIES", value_delimiter = ',', value_parser = peer::parse_ip_net, value_name = "CIDR")]
+    trusted_proxies: Vec<IpNet>,
+    /// Nameserver accepting RFC 2136 dynamic updates for --dns-zone, enables publishing the client addresses in DNS
+    #[arg(long, env = "HOST_EXPOSER_SERVER_DNS_SERVER", value_name = "HOST:PORT", requires_all = ["dns_zone", "dns_tsig_key_name", "dns_tsig_secret"])]
+    dns_server: Option<String>,
+    /// Zone the client hostnames are published in
+    #[arg(long, env = "HOST_EXPOSER_SERVER_DNS_ZONE", value_name = "ZONE")]
+    dns_zone: Option<String>,
+    /// Name of the TSIG key signing the dynamic updates
+    #[arg(long, env = "HOST_EXPOSER_SERVER_DNS_TSIG_KEY_NAME", value_name = "NAME")]
+    dns_tsig_key_name: Option<String>,
+    /// Base64 encoded secret of the TSIG key
+    #[arg(long, env = "HOST_EXPOSER_SERVER_DNS_TSIG_SECRET", value_name = "SECRET")]
+    dns_tsig_secret: Option<String>,
+    /// Algorithm of the TSIG key
+    #[arg(long, env = "HOST_EXPOSER_SERVER_DNS_TSIG_ALGORITHM", value_enum, default_value_t)]
+    dns_tsig_algorithm: TsigAlgorithm,
+    /// TTL of the published records
+    #[arg(long, env = "HOST_EXPOSER_SERVER_DNS_TTL", default_value = "300", value_name = "SECONDS")]
+    dns_ttl: u32,
+    /// Maximum time to wait for the nameserver to answer a dynamic update
+    #[arg(long, env = "HOST_EXPOSER_SERVER_DNS_TIMEOUT", default_value = "5", value_parser = clap::value_parser!(u64).range(1..), value_name = "SECONDS")]
+    dns_timeout: u64,
+    /// Maximum number of attempts of delivering an event to a webhook
+    #[arg(long, env = "HOST_EXPOSER_SERVER_WEBHOOK_MAX_ATTEMPTS", default_value = "5", value_name = "ATTEMPTS")]
+    webhook_max_attempts: u32,
+    /// Maximum time to wait for a webhook endpoint to respond
+    #[arg(long, env = "HOST_EXPOSER_SERVER_WEBHOOK_TIMEOUT", default_value = "10", value_parser = clap::value_parser!(u64).range(1..), value_name = "SECONDS")]
+    webhook_timeout: u64,
+    /// Let the names and tags declared by the clients replace the ones set through the REST API,
+    /// otherwise they are only used until an admin sets them
+    #[arg(long, env = "HOST_EXPOSER_SERVER_CLIENT_LABELS_OVERRIDE")]
+    client_labels_override: bool,
+    /// Archive the clients not seen for this many days, up to 36500, never if not specified
+    #[arg(long, env = "HOST_EXPOSER_SERVER_PRUNE_AFTER", value_name = "DAYS", value_parser = clap::value_parser!(u64).range(1..=36500))]
+    prune_after: Option<u64>,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Print the Argon2 hash of a password for --pwd-hash, the password is prompted for without echo, or read from stdin if it is not a terminal
+    HashPassword,
+    /// Apply all pending database migrations and exit
+    Migrate {
+        /// Revert the given number of applied migrations instead
+        #[arg(long, value_name = "STEPS")]
+        down: Option<u32>,
+    },
 }
 
 #[derive(Clone)]
 struct AppState {
     db: DatabaseConnection,
     clients: Clients,
-    server_base64_password: String,
+    password_verifier: Arc<PasswordVerifier>,
     default_offset: UtcOffset,
+    refresh_timeout: Duration,
+    heartbeat_interval: Duration,
+    heartbeat_timeout: Duration,
+    handshake_timeout: Duration,
+    auth_limiter: Arc<AuthFailureLimiter>,
+    allow_password_clients: bool,
+    client_labels_override: bool,
+    trusted_proxies: Arc<Vec<IpNet>>,
+    dns_updater: Option<Arc<dyn DnsUpdater>>,
+    dns_publishes: Arc<DnsPublishQueue>,
+    events: broadcast::Sender<ClientEvent>,
+    metrics: Arc<Metrics>,
 }
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let args = Args::parse();
+    let args: Args = parse_args_with_config_file();
+
+    if let Some(Command::HashPassword) = args.command {
+        // never from the arguments, which end up in the shell history and the process list
+        let password = if std::io::stdin().is_terminal() {
+            rpassword::prompt_password("Password: ")?
+        } else {
+            std::io::stdin().lock().lines().next().transpose()?.unwrap_or_default()
+        };
+        println!("{}", auth::hash_password(&password)?);
+        return Ok(());
+    }
 
     tracing_subscriber::fmt()
         .with_timer(tracing_timer(args.default_offset))
         .with_max_level(args.max_log_level).init();
 
-    let db = setup_db_connection().await?;
+    if let Some(Command::Migrate { down }) = args.command {
+        let db = connect_db(&args.database_url).await?;
+        match down {
+            Some(steps) => Migrator::down(&db, Some(steps)).await?,
+            None => Migrator::up(&db, None).await?,
+        }
+        return Ok(());
+    }
+
+    let db = setup_db_connection(&args.database_url).await?;
 
-    let password = match args.pwd {
-        Some(pwd) => pwd,
-        None => {
+    let password_hash = match (args.pwd_hash, args.pwd_hash_file, args.pwd, args.pwd_file) {
+        (Some(pwd_hash), _, _, _) => pwd_hash,
+        (_, Some(pwd_hash_file), _, _) => read_secret_file(pwd_hash_file)?.trim().to_string(),
+        (_, _, Some(pwd), _) => auth::hash_password(&pwd)?,
+        (_, _, _, Some(pwd_file)) => auth::hash_password(&read_secret_file(pwd_file)?)?,
+        (None, None, None, None) => {
             let result = auth::random_password(args.random_password_length);
             warn!("No password specified, generated random password: {}", result);
-            result
+            auth::hash_password(&result)?
         }
     };
-    let base64_password = BASE64_STANDARD.encode(password);
+    let password_verifier = Arc::new(PasswordVerifier::new(password_hash)?);
+    if args.allow_password_clients {
+        warn!("Clients authenticating with the server password are accepted, which is deprecated: issue them tokens from the web UI and drop --allow-password-clients");
+    }
 
-    let state = AppState { db, clients: Clients::default(), server_base64_password: base64_password, default_offset: args.default_offset };
+    let dns_updater: Option<Arc<dyn DnsUpdater>> = match (args.dns_server, args.dns_zone, args.dns_tsig_key_name, args.dns_tsig_secret) {
+        (Some(dns_server), Some(dns_zone), Some(key_name), Some(secret)) => {
+            let key = TsigKey {
+                name: key_name,
+                algorithm: args.dns_tsig_algorithm,
+                secret: BASE64_STANDARD.decode(secret.trim())?,
+            };
+            info!("Publishing client addresses in zone {} through {}", &dns_zone, &dns_server);
+            Some(Arc::new(Rfc2136Updater::new(dns_server, &dns_zone, args.dns_ttl, key, Duration::from_secs(args.dns_timeout))?))
+        }
+        _ => None,
+    };
+
+    let state = AppState {
+        db,
+        clients: Clients::default(),
+        password_verifier,
+        default_offset: args.default_offset,
+        refresh_timeout: Duration::from_secs(args.refresh_timeout),
+        heartbeat_interval: Duration::from_secs(args.heartbeat_interval),
+        heartbeat_timeout: Duration::from_secs(args.heartbeat_timeout),
+        handshake_timeout: Duration::from_secs(args.handshake_timeout),
+        auth_limiter: Arc::new(AuthFailureLimiter::new(
+            args.auth_max_failures,
+            Duration::from_secs(args.auth_failure_window),
+            Duration::from_secs(args.auth_ban_duration),
+        )),
+        allow_password_clients: args.allow_password_clients,
+        client_labels_override: args.client_labels_override,
+        trusted_proxies: Arc::new(args.trusted_proxies),
+        dns_updater,
+        dns_publishes: Arc::new(DnsPublishQueue::default()),
+        events: broadcast::channel(256).0,
+        metrics: Arc::new(Metrics::default()),
+    };
+    webhooks::spawn_webhook_dispatcher(state.clone(), args.webhook_max_attempts, Duration::from_secs(args.webhook_timeout))?;
+    if let Some(prune_after) = args.prune_after {
+        clients::spawn_client_pruner(state.clone(), Duration::from_secs(prune_after * 24 * 60 * 60));
+    }
 
     let client_rest_router = Router::new()
         .route("/", get(clients::get_clients_information))
         .route("/auth", get(move || async move {}))
-        .route("/:id", put(clients::modify_client_name))
+        .route("/:id", put(clients::modify_client_name).delete(clients::delete_client))
+        .route("/:id/archive", put(clients::archive_client).delete(clients::unarchive_client))
+        .route("/:id/tags", put(clients::modify_client_tags))
+        .route("/:id/group", put(clients::modify_client_group))
+        .route("/:id/notes", put(clients::modify_client_notes))
+        .route("/:id/history", get(clients::get_client_address_history))
+        .route("/:id/dns", get(dns::get_dns_records).post(dns::create_dns_record))
+        .route("/:id/dns/:record_id", delete(dns::delete_dns_record))
+        .route_layer(middleware::from_fn_with_state(state.clone(), basic_auth))
+        .with_state(state.clone());
+
+    let token_rest_router = Router::new()
+        .route("/", get(tokens::get_tokens).post(tokens::issue_token))
+        .route("/:id", delete(tokens::revoke_token))
+        .route_layer(middleware::from_fn_with_state(state.clone(), basic_auth))
+        .with_state(state.clone());
+
+    let webhook_rest_router = Router::new()
+        .route("/", get(webhooks::get_webhooks).post(webhooks::create_webhook))
+        .route("/:id", delete(webhooks::delete_webhook))
+        .route("/:id/deliveries", get(webhooks::get_webhook_deliveries))
+        .route_layer(middleware::from_fn_with_state(state.clone(), basic_auth))
+        .with_state(state.clone());
+
+    let event_rest_router = Router::new()
+        .route("/", get(events::stream_events))
+        .route_layer(middleware::from_fn_with_state(state.clone(), basic_auth))
+        .with_state(state.clone());
+
+    let metrics_rest_router = Router::new()
+        .route("/", get(metrics::get_metrics))
         .route_layer(middleware::from_fn_with_state(state.clone(), basic_auth))
         .with_state(state.clone());
 
     let app = Router::new()
         .route("/expose", get(clients::handle_expose_websocket))
         .nest("/api/client", client_rest_router)
+        .nest("/api/token", token_rest_router)
+        .nest("/api/webhook", webhook_rest_router)
+        .nest("/api/events", event_rest_router)
+        .nest("/api/metrics", metrics_rest_router)
         .nest_service("/", ServeEmbed::<AppWebPages>::with_parameters(
             None,
             FallbackBehavior::NotFound,
             Some("index.html".to_owned()),
         ))
         .with_state(state.clone());
-    let listener = tokio::net::TcpListener::bind(format!("0.0.0.0:{}", args.port)).await?;
-    axum::serve(listener, app).await?;
+    let address = SocketAddr::from(([0, 0, 0, 0], args.port));
+    match (args.tls_cert, args.tls_key) {
+        (Some(tls_cert), Some(tls_key)) => {
+            let config = tls::load_rustls_config(&tls_cert, &tls_key).await?;
+            tls::spawn_certificate_reloader(config.clone(), tls_cert, tls_key, Duration::from_secs(args.tls_reload_interval));
+            info!("Listening on https://{}", address);
+            axum_server::bind_rustls(address, config)
+                .serve(app.into_make_service_with_connect_info::<SocketAddr>())
+                .await?;
+        }
+        _ => {
+            let listener = tokio::net::TcpListener::bind(address).await?;
+            info!("Listening on http://{}", address);
+            axum::serve(listener, app.into_make_service_with_connect_info::<SocketAddr>()).await?;
+        }
+    }
     Ok(())
 }
 
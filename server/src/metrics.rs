@@ -0,0 +1,42 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::Mutex;
+
+use axum::extract::State;
+use axum::http::header;
+use axum::response::IntoResponse;
+
+use crate::AppState;
+use crate::result::HEError;
+
+/// Counters of the server, exposed at `/api/metrics` in the Prometheus text format
+#[derive(Default)]
+pub struct Metrics {
+    /// Errors ending a client connection or reported back to the client, by kind
+    connection_errors: Mutex<BTreeMap<&'static str, u64>>,
+}
+
+impl Metrics {
+    pub fn record_connection_error(&self, error: &HEError) {
+        let mut connection_errors = self.connection_errors.lock().unwrap_or_else(|e| e.into_inner());
+        *connection_errors.entry(error.kind()).or_default() += 1;
+    }
+}
+
+pub async fn get_metrics(State(state): State<AppState>) -> impl IntoResponse {
+    let mut body = String::new();
+    let connected_clients = state.clients.read().await.len();
+    writeln!(body, "# HELP host_exposer_connected_clients Clients currently connected.").ok();
+    writeln!(body, "# TYPE host_exposer_connected_clients gauge").ok();
+    writeln!(body, "host_exposer_connected_clients {}", connected_clients).ok();
+    writeln!(body, "# HELP host_exposer_banned_addresses Addresses currently banned for failing to authenticate too often.").ok();
+    writeln!(body, "# TYPE host_exposer_banned_addresses gauge").ok();
+    writeln!(body, "host_exposer_banned_addresses {}", state.auth_limiter.banned_count()).ok();
+    writeln!(body, "# HELP host_exposer_connection_errors_total Errors in the conversation with the clients, by kind.").ok();
+    writeln!(body, "# TYPE host_exposer_connection_errors_total counter").ok();
+    let connection_errors = state.metrics.connection_errors.lock().unwrap_or_else(|e| e.into_inner()).clone();
+    for (kind, count) in connection_errors {
+        writeln!(body, "host_exposer_connection_errors_total{{kind=\"{}\"}} {}", kind, count).ok();
+    }
+    ([(header::CONTENT_TYPE, "text/plain; version=0.0.4")], body)
+}
@@ -0,0 +1,76 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(AddressHistory::Table)
+                    .if_not_exists()
+                    .col(
+                        ColumnDef::new(AddressHistory::Id)
+                            .big_integer()
+                            .not_null()
+                            .auto_increment()
+                            .primary_key(),
+                    )
+                    .col(
+                        ColumnDef::new(AddressHistory::ClientId).uuid().not_null()
+                    )
+                    .col(
+                        ColumnDef::new(AddressHistory::Addresses).json().not_null()
+                    )
+                    // written in UTC, SQLite compares them as text
+                    .col(
+                        ColumnDef::new(AddressHistory::FirstSeenTime).timestamp_with_time_zone().not_null()
+                    )
+                    .col(
+                        ColumnDef::new(AddressHistory::LastSeenTime).timestamp_with_time_zone().not_null()
+                    )
+                    .foreign_key(
+                        ForeignKey::create()
+                            .from(AddressHistory::Table, AddressHistory::ClientId)
+                            .to(Client::Table, Client::Id)
+                            .on_delete(ForeignKeyAction::Cascade)
+                    )
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx_address_history_client_id_last_seen_time")
+                    .table(AddressHistory::Table)
+                    .col(AddressHistory::ClientId)
+                    .col(AddressHistory::LastSeenTime)
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(AddressHistory::Table).to_owned())
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+enum AddressHistory {
+    Table,
+    Id,
+    ClientId,
+    Addresses,
+    FirstSeenTime,
+    LastSeenTime,
+}
+
+#[derive(DeriveIden)]
+enum Client {
+    Table,
+    Id,
+}
@@ -0,0 +1,68 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(ClientToken::Table)
+                    .if_not_exists()
+                    .col(
+                        ColumnDef::new(ClientToken::Id)
+                            .uuid()
+                            .not_null()
+                            .primary_key(),
+                    )
+                    .col(
+                        ColumnDef::new(ClientToken::ClientId).uuid().null()
+                    )
+                    .col(
+                        ColumnDef::new(ClientToken::TokenHash).string().not_null().unique_key()
+                    )
+                    .col(
+                        ColumnDef::new(ClientToken::Description).string().null()
+                    )
+                    .col(
+                        ColumnDef::new(ClientToken::CreateTime).timestamp_with_time_zone().not_null()
+                    )
+                    .col(
+                        ColumnDef::new(ClientToken::LastUsedTime).timestamp_with_time_zone().null()
+                    )
+                    .foreign_key(
+                        ForeignKey::create()
+                            .from(ClientToken::Table, ClientToken::ClientId)
+                            .to(Client::Table, Client::Id)
+                            .on_delete(ForeignKeyAction::Cascade)
+                    )
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(ClientToken::Table).to_owned())
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+enum ClientToken {
+    Table,
+    Id,
+    ClientId,
+    TokenHash,
+    Description,
+    CreateTime,
+    LastUsedTime,
+}
+
+#[derive(DeriveIden)]
+enum Client {
+    Table,
+    Id,
+}
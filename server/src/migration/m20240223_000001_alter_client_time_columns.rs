@@ -0,0 +1,47 @@
+use sea_orm_migration::prelude::*;
+use sea_orm_migration::sea_orm::DbBackend;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        // `OffsetDateTime` needs `timestamptz` on PostgreSQL, SQLite keeps both types as the very
+        // same text and can not modify columns anyway
+        if manager.get_database_backend() == DbBackend::Sqlite {
+            return Ok(());
+        }
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Client::Table)
+                    .modify_column(ColumnDef::new(Client::CreateTime).timestamp_with_time_zone().not_null())
+                    .modify_column(ColumnDef::new(Client::LastFetchTime).timestamp_with_time_zone().not_null())
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        if manager.get_database_backend() == DbBackend::Sqlite {
+            return Ok(());
+        }
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Client::Table)
+                    .modify_column(ColumnDef::new(Client::CreateTime).date_time().not_null())
+                    .modify_column(ColumnDef::new(Client::LastFetchTime).date_time().not_null())
+                    .to_owned(),
+            )
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+enum Client {
+    Table,
+    CreateTime,
+    LastFetchTime,
+}
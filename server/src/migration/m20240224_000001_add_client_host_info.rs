@@ -0,0 +1,62 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        // one column per statement, SQLite cannot add several columns in a single ALTER TABLE
+        for mut column in [
+            ColumnDef::new(Client::Hostname).string().null().to_owned(),
+            ColumnDef::new(Client::OsVersion).string().null().to_owned(),
+            ColumnDef::new(Client::KernelVersion).string().null().to_owned(),
+            ColumnDef::new(Client::Arch).string().null().to_owned(),
+            ColumnDef::new(Client::ClientVersion).string().null().to_owned(),
+            ColumnDef::new(Client::BootTime).timestamp_with_time_zone().null().to_owned(),
+        ] {
+            manager
+                .alter_table(
+                    Table::alter()
+                        .table(Client::Table)
+                        .add_column(&mut column)
+                        .to_owned(),
+                )
+                .await?;
+        }
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        for column in [
+            Client::Hostname,
+            Client::OsVersion,
+            Client::KernelVersion,
+            Client::Arch,
+            Client::ClientVersion,
+            Client::BootTime,
+        ] {
+            manager
+                .alter_table(
+                    Table::alter()
+                        .table(Client::Table)
+                        .drop_column(column)
+                        .to_owned(),
+                )
+                .await?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(DeriveIden)]
+#[allow(clippy::enum_variant_names)]
+enum Client {
+    Table,
+    Hostname,
+    OsVersion,
+    KernelVersion,
+    Arch,
+    ClientVersion,
+    BootTime,
+}
@@ -0,0 +1,69 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(DnsRecord::Table)
+                    .if_not_exists()
+                    .col(
+                        ColumnDef::new(DnsRecord::Id)
+                            .big_integer()
+                            .not_null()
+                            .auto_increment()
+                            .primary_key(),
+                    )
+                    .col(
+                        ColumnDef::new(DnsRecord::ClientId).uuid().not_null()
+                    )
+                    .col(
+                        ColumnDef::new(DnsRecord::Hostname).string().not_null().unique_key()
+                    )
+                    .col(
+                        ColumnDef::new(DnsRecord::Adapter).string().not_null()
+                    )
+                    .col(
+                        ColumnDef::new(DnsRecord::PublishedAddresses).json().null()
+                    )
+                    .col(
+                        ColumnDef::new(DnsRecord::LastUpdateTime).timestamp_with_time_zone().null()
+                    )
+                    .foreign_key(
+                        ForeignKey::create()
+                            .from(DnsRecord::Table, DnsRecord::ClientId)
+                            .to(Client::Table, Client::Id)
+                            .on_delete(ForeignKeyAction::Cascade)
+                    )
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(DnsRecord::Table).to_owned())
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+enum DnsRecord {
+    Table,
+    Id,
+    ClientId,
+    Hostname,
+    Adapter,
+    PublishedAddresses,
+    LastUpdateTime,
+}
+
+#[derive(DeriveIden)]
+enum Client {
+    Table,
+    Id,
+}
@@ -0,0 +1,128 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(Webhook::Table)
+                    .if_not_exists()
+                    .col(
+                        ColumnDef::new(Webhook::Id)
+                            .uuid()
+                            .not_null()
+                            .primary_key(),
+                    )
+                    .col(
+                        ColumnDef::new(Webhook::Url).string().not_null()
+                    )
+                    .col(
+                        ColumnDef::new(Webhook::Secret).string().null()
+                    )
+                    .col(
+                        ColumnDef::new(Webhook::Events).json().not_null()
+                    )
+                    .col(
+                        ColumnDef::new(Webhook::Description).string().null()
+                    )
+                    .col(
+                        ColumnDef::new(Webhook::CreateTime).timestamp_with_time_zone().not_null()
+                    )
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .create_table(
+                Table::create()
+                    .table(WebhookDelivery::Table)
+                    .if_not_exists()
+                    .col(
+                        ColumnDef::new(WebhookDelivery::Id)
+                            .big_integer()
+                            .not_null()
+                            .auto_increment()
+                            .primary_key(),
+                    )
+                    .col(
+                        ColumnDef::new(WebhookDelivery::WebhookId).uuid().not_null()
+                    )
+                    .col(
+                        ColumnDef::new(WebhookDelivery::Event).string().not_null()
+                    )
+                    .col(
+                        ColumnDef::new(WebhookDelivery::Payload).json().not_null()
+                    )
+                    .col(
+                        ColumnDef::new(WebhookDelivery::Attempt).integer().not_null()
+                    )
+                    .col(
+                        ColumnDef::new(WebhookDelivery::StatusCode).integer().null()
+                    )
+                    .col(
+                        ColumnDef::new(WebhookDelivery::Error).string().null()
+                    )
+                    .col(
+                        ColumnDef::new(WebhookDelivery::Success).boolean().not_null()
+                    )
+                    .col(
+                        ColumnDef::new(WebhookDelivery::DeliveryTime).timestamp_with_time_zone().not_null()
+                    )
+                    .foreign_key(
+                        ForeignKey::create()
+                            .from(WebhookDelivery::Table, WebhookDelivery::WebhookId)
+                            .to(Webhook::Table, Webhook::Id)
+                            .on_delete(ForeignKeyAction::Cascade)
+                    )
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx_webhook_delivery_webhook_id_delivery_time")
+                    .table(WebhookDelivery::Table)
+                    .col(WebhookDelivery::WebhookId)
+                    .col(WebhookDelivery::DeliveryTime)
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(WebhookDelivery::Table).to_owned())
+            .await?;
+        manager
+            .drop_table(Table::drop().table(Webhook::Table).to_owned())
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+enum Webhook {
+    Table,
+    Id,
+    Url,
+    Secret,
+    Events,
+    Description,
+    CreateTime,
+}
+
+#[derive(DeriveIden)]
+enum WebhookDelivery {
+    Table,
+    Id,
+    WebhookId,
+    Event,
+    Payload,
+    Attempt,
+    StatusCode,
+    Error,
+    Success,
+    DeliveryTime,
+}
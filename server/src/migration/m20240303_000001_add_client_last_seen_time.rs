@@ -0,0 +1,51 @@
+use sea_orm_migration::prelude::*;
+use sea_orm_migration::sea_orm::DbBackend;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Client::Table)
+                    .add_column(ColumnDef::new(Client::LastSeenTime).timestamp_with_time_zone().null())
+                    .to_owned(),
+            )
+            .await?;
+        // the last fetch is the best guess for the clients known before, the last seen time is kept
+        // in UTC, which SQLite, storing the times as text, needs converting to
+        let last_fetch_time = match manager.get_database_backend() {
+            DbBackend::Sqlite => Expr::cust("strftime('%Y-%m-%dT%H:%M:%fZ', \"last_fetch_time\")"),
+            _ => Expr::col(Client::LastFetchTime).into(),
+        };
+        manager
+            .exec_stmt(
+                Query::update()
+                    .table(Client::Table)
+                    .value(Client::LastSeenTime, last_fetch_time)
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Client::Table)
+                    .drop_column(Client::LastSeenTime)
+                    .to_owned(),
+            )
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+enum Client {
+    Table,
+    LastFetchTime,
+    LastSeenTime,
+}
@@ -0,0 +1,89 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        for mut column in [
+            ColumnDef::new(Client::Group).string().null().to_owned(),
+            ColumnDef::new(Client::Notes).text().null().to_owned(),
+        ] {
+            manager
+                .alter_table(
+                    Table::alter()
+                        .table(Client::Table)
+                        .add_column(&mut column)
+                        .to_owned(),
+                )
+                .await?;
+        }
+        manager
+            .create_table(
+                Table::create()
+                    .table(ClientTag::Table)
+                    .if_not_exists()
+                    .col(
+                        ColumnDef::new(ClientTag::ClientId).uuid().not_null()
+                    )
+                    .col(
+                        ColumnDef::new(ClientTag::Tag).string().not_null()
+                    )
+                    .primary_key(
+                        Index::create()
+                            .col(ClientTag::ClientId)
+                            .col(ClientTag::Tag)
+                    )
+                    .foreign_key(
+                        ForeignKey::create()
+                            .from(ClientTag::Table, ClientTag::ClientId)
+                            .to(Client::Table, Client::Id)
+                            .on_delete(ForeignKeyAction::Cascade)
+                    )
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx_client_tag_tag")
+                    .table(ClientTag::Table)
+                    .col(ClientTag::Tag)
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(ClientTag::Table).to_owned())
+            .await?;
+        for column in [Client::Group, Client::Notes] {
+            manager
+                .alter_table(
+                    Table::alter()
+                        .table(Client::Table)
+                        .drop_column(column)
+                        .to_owned(),
+                )
+                .await?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(DeriveIden)]
+enum Client {
+    Table,
+    Id,
+    Group,
+    Notes,
+}
+
+#[derive(DeriveIden)]
+enum ClientTag {
+    Table,
+    ClientId,
+    Tag,
+}
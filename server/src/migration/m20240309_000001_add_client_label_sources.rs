@@ -0,0 +1,70 @@
+use sea_orm_migration::prelude::*;
+use sea_orm_migration::sea_orm::ConnectionTrait;
+use uuid::Uuid;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        for mut column in [
+            ColumnDef::new(Client::NameSetByAdmin).boolean().not_null().default(false).to_owned(),
+            ColumnDef::new(Client::TagsSetByAdmin).boolean().not_null().default(false).to_owned(),
+        ] {
+            manager
+                .alter_table(
+                    Table::alter()
+                        .table(Client::Table)
+                        .add_column(&mut column)
+                        .to_owned(),
+                )
+                .await?;
+        }
+        // names given before clients could declare their own were all given by an admin,
+        // the ids are compared here as their column type differs between the backends
+        let db = manager.get_connection();
+        let builder = db.get_database_backend();
+        let rows = db.query_all(builder.build(
+            Query::select()
+                .columns([Client::Id, Client::Name])
+                .from(Client::Table)
+        )).await?;
+        for row in rows {
+            let id: Uuid = row.try_get("", "id")?;
+            let name: String = row.try_get("", "name")?;
+            if name != id.to_string() {
+                db.execute(builder.build(
+                    Query::update()
+                        .table(Client::Table)
+                        .value(Client::NameSetByAdmin, true)
+                        .and_where(Expr::col(Client::Id).eq(id))
+                )).await?;
+            }
+        }
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        for column in [Client::NameSetByAdmin, Client::TagsSetByAdmin] {
+            manager
+                .alter_table(
+                    Table::alter()
+                        .table(Client::Table)
+                        .drop_column(column)
+                        .to_owned(),
+                )
+                .await?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(DeriveIden)]
+enum Client {
+    Table,
+    Id,
+    Name,
+    NameSetByAdmin,
+    TagsSetByAdmin,
+}
@@ -1,11 +1,35 @@
 use sea_orm_migration::{MigrationTrait, MigratorTrait};
 
 pub mod m20240218_000001_create_client_table;
+pub mod m20240220_000001_create_address_history_table;
+pub mod m20240222_000001_create_client_token_table;
+pub mod m20240223_000001_alter_client_time_columns;
+pub mod m20240224_000001_add_client_host_info;
+pub mod m20240226_000001_add_client_public_addresses;
+pub mod m20240228_000001_create_dns_record_table;
+pub mod m20240301_000001_create_webhook_tables;
+pub mod m20240303_000001_add_client_last_seen_time;
+pub mod m20240305_000001_add_client_archived;
+pub mod m20240307_000001_add_client_tags_and_notes;
+pub mod m20240309_000001_add_client_label_sources;
 
 pub struct Migrator;
 
 impl MigratorTrait for Migrator {
     fn migrations() -> Vec<Box<dyn MigrationTrait>> {
-        vec![Box::new(m20240218_000001_create_client_table::Migration)]
+        vec![
+            Box::new(m20240218_000001_create_client_table::Migration),
+            Box::new(m20240220_000001_create_address_history_table::Migration),
+            Box::new(m20240222_000001_create_client_token_table::Migration),
+            Box::new(m20240223_000001_alter_client_time_columns::Migration),
+            Box::new(m20240224_000001_add_client_host_info::Migration),
+            Box::new(m20240226_000001_add_client_public_addresses::Migration),
+            Box::new(m20240228_000001_create_dns_record_table::Migration),
+            Box::new(m20240301_000001_create_webhook_tables::Migration),
+            Box::new(m20240303_000001_add_client_last_seen_time::Migration),
+            Box::new(m20240305_000001_add_client_archived::Migration),
+            Box::new(m20240307_000001_add_client_tags_and_notes::Migration),
+            Box::new(m20240309_000001_add_client_label_sources::Migration),
+        ]
     }
 }
@@ -0,0 +1,36 @@
+use std::net::{IpAddr, SocketAddr};
+use std::str::FromStr;
+
+use axum::http::HeaderMap;
+use ipnet::IpNet;
+
+pub fn parse_ip_net(s: &str) -> Result<IpNet, String> {
+    IpNet::from_str(s)
+        .or_else(|_| IpAddr::from_str(s).map(IpNet::from))
+        .map_err(|_| format!("'{}' is neither an IP address nor a CIDR network", s))
+}
+
+/// Address of the client behind the connection, when the connection comes from a trusted proxy
+/// the `X-Forwarded-For` header is walked from the right, and the first hop not being one of the
+/// trusted proxies is taken, as everything left of it may have been forged by the client.
+pub fn resolve_peer_address(peer: SocketAddr, headers: &HeaderMap, trusted_proxies: &[IpNet]) -> IpAddr {
+    let is_trusted = |ip: &IpAddr| trusted_proxies.iter().any(|net| net.contains(ip));
+    let mut result = peer.ip().to_canonical();
+    if !is_trusted(&result) {
+        return result;
+    }
+    let forwarded_hops = headers.get_all("x-forwarded-for").iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .collect::<Vec<_>>();
+    for hop in forwarded_hops.into_iter().rev() {
+        let Ok(ip) = IpAddr::from_str(hop.trim()) else {
+            break;
+        };
+        result = ip.to_canonical();
+        if !is_trusted(&result) {
+            break;
+        }
+    }
+    result
+}
@@ -1,3 +1,4 @@
+use axum::extract::ws::close_code;
 use axum::http::StatusCode;
 use axum::response::{IntoResponse, Response};
 use tokio::sync::mpsc::error::SendError;
@@ -12,7 +13,67 @@ pub enum HEError {
     #[error("an error occurred while performing message communication: {0}")]
     Message(String),
     #[error("an error occurred while performing database operations: {0:?}")]
-    Db(#[from] DbErr)
+    Db(#[from] DbErr),
+    #[error("{0}")]
+    NotFound(String),
+    #[error("{0}")]
+    BadRequest(String),
+    #[error("{0}")]
+    Conflict(String),
+    #[error("an error occurred while updating DNS records: {0}")]
+    Dns(String),
+    /// A client sent a message which could not be decoded
+    #[error("malformed message: {0}")]
+    MalformedMessage(String),
+    /// A client sent a frame or message not expected at this point of the conversation
+    #[error("unexpected message: {0}")]
+    UnexpectedMessage(String),
+    #[error("{0}")]
+    Unauthorized(String),
+    /// A client speaking a protocol version this server no longer speaks
+    #[error("{0}")]
+    IncompatibleProtocol(String),
+    /// An address banned after failing to authenticate too often
+    #[error("{0}")]
+    TooManyRequests(String),
+    #[error("timed out: {0}")]
+    Timeout(String),
+}
+
+impl HEError {
+    /// Short name of the kind of the error, used to label the metrics
+    pub fn kind(&self) -> &'static str {
+        match self {
+            HEError::Io(_) => "io",
+            HEError::Message(_) => "message",
+            HEError::Db(_) => "db",
+            HEError::NotFound(_) => "not_found",
+            HEError::BadRequest(_) => "bad_request",
+            HEError::Conflict(_) => "conflict",
+            HEError::Dns(_) => "dns",
+            HEError::MalformedMessage(_) => "malformed_message",
+            HEError::UnexpectedMessage(_) => "unexpected_message",
+            HEError::Unauthorized(_) => "unauthorized",
+            HEError::IncompatibleProtocol(_) => "incompatible_protocol",
+            HEError::TooManyRequests(_) => "too_many_requests",
+            HEError::Timeout(_) => "timeout",
+        }
+    }
+
+    /// Websocket close code of a connection ended by this error
+    pub fn close_code(&self) -> u16 {
+        match self {
+            HEError::MalformedMessage(_) => close_code::INVALID,
+            HEError::UnexpectedMessage(_) | HEError::IncompatibleProtocol(_) => close_code::PROTOCOL,
+            HEError::Unauthorized(_) | HEError::TooManyRequests(_) | HEError::Timeout(_) => close_code::POLICY,
+            _ => close_code::ERROR,
+        }
+    }
+
+    /// Whether the error is caused by the client, the others are not detailed to it
+    pub fn is_client_error(&self) -> bool {
+        self.close_code() != close_code::ERROR
+    }
 }
 
 impl <T> From<SendError<T>> for HEError {
@@ -23,6 +84,15 @@ impl <T> From<SendError<T>> for HEError {
 
 impl IntoResponse for HEError {
     fn into_response(self) -> Response {
-        (StatusCode::INTERNAL_SERVER_ERROR, self.to_string()).into_response()
+        let status = match self {
+            HEError::NotFound(_) => StatusCode::NOT_FOUND,
+            HEError::BadRequest(_) => StatusCode::BAD_REQUEST,
+            HEError::Conflict(_) => StatusCode::CONFLICT,
+            HEError::Dns(_) => StatusCode::BAD_GATEWAY,
+            HEError::Unauthorized(_) => StatusCode::UNAUTHORIZED,
+            HEError::TooManyRequests(_) => StatusCode::TOO_MANY_REQUESTS,
+            _ => StatusCode::INTERNAL_SERVER_ERROR,
+        };
+        (status, self.to_string()).into_response()
     }
 }
\ No newline at end of file
@@ -0,0 +1,42 @@
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+use axum_server::tls_rustls::RustlsConfig;
+use tracing::{error, info};
+
+pub async fn load_rustls_config(cert: &Path, key: &Path) -> io::Result<RustlsConfig> {
+    RustlsConfig::from_pem_file(cert, key).await
+}
+
+/// Polls the modification time of the certificate and key files, reloading the configuration
+/// in place once either of them changed so that renewed certificates are picked up without restart.
+pub fn spawn_certificate_reloader(config: RustlsConfig, cert: PathBuf, key: PathBuf, interval: Duration) {
+    tokio::spawn(async move {
+        let mut last_modified = modified_times(&cert, &key).await;
+        let mut ticker = tokio::time::interval(interval);
+        ticker.reset();
+        loop {
+            ticker.tick().await;
+            let modified = modified_times(&cert, &key).await;
+            if modified == last_modified {
+                continue;
+            }
+            match config.reload_from_pem_file(&cert, &key).await {
+                Ok(()) => {
+                    info!("Reloaded TLS certificate from {}", cert.display());
+                    last_modified = modified;
+                }
+                // the files may be in the middle of being replaced, retry on the next tick
+                Err(e) => error!("Failed to reload TLS certificate from {}: {}", cert.display(), e),
+            }
+        }
+    });
+}
+
+async fn modified_times(cert: &Path, key: &Path) -> (Option<SystemTime>, Option<SystemTime>) {
+    async fn modified_time(path: &Path) -> Option<SystemTime> {
+        tokio::fs::metadata(path).await.and_then(|metadata| metadata.modified()).ok()
+    }
+    (modified_time(cert).await, modified_time(key).await)
+}
@@ -0,0 +1,61 @@
+use axum::extract::{Path, State};
+use axum::Json;
+use sea_orm::EntityTrait;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use tracing::info;
+use uuid::Uuid;
+
+use crate::{AppState, db};
+use crate::auth::random_token;
+use crate::entity::client_token;
+use crate::entity::prelude::DbClient;
+use crate::result::HEError;
+
+#[derive(Deserialize)]
+pub struct IssueTokenBody {
+    /// Bind the token to an already known client, otherwise it is bound to the first client using it
+    #[serde(default)]
+    client_id: Option<Uuid>,
+    #[serde(default)]
+    description: Option<String>,
+}
+
+/// Issues a new enrollment token, the plain token is only returned here and cannot be retrieved later
+pub async fn issue_token(
+    State(state): State<AppState>,
+    Json(body): Json<IssueTokenBody>,
+) -> Result<Json<Value>, HEError> {
+    let db = &state.db;
+    if let Some(client_id) = body.client_id {
+        if DbClient::find_by_id(client_id).one(db).await?.is_none() {
+            return Err(HEError::NotFound(format!("client {} not found", client_id)));
+        }
+    }
+    let token = random_token();
+    let entity = db::client_token::issue_token(&token, body.client_id, body.description, db, &state.default_offset).await?;
+    Ok(Json(json!({
+        "entity": entity,
+        "token": token
+    })))
+}
+
+pub async fn get_tokens(State(state): State<AppState>) -> Result<Json<Vec<client_token::Model>>, HEError> {
+    Ok(Json(db::client_token::find_tokens(&state.db).await?))
+}
+
+pub async fn revoke_token(
+    State(state): State<AppState>,
+    Path(id): Path<Uuid>,
+) -> Result<(), HEError> {
+    let Some(revoked) = db::client_token::revoke_token(&id, &state.db).await? else {
+        return Err(HEError::NotFound(format!("token {} not found", id)));
+    };
+    if let Some(client_id) = revoked.client_id {
+        if let Some(client) = state.clients.read().await.get(&client_id) {
+            info!("Token {} revoked, disconnecting client {}", &id, &client_id);
+            client.disconnect();
+        }
+    }
+    Ok(())
+}
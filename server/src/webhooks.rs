@@ -0,0 +1,204 @@
+use std::time::Duration;
+
+use axum::extract::{Path, Query, State};
+use axum::Json;
+use hmac::{Hmac, Mac};
+use sea_orm::EntityTrait;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use sha2::Sha256;
+use tokio::sync::broadcast::error::RecvError;
+use tracing::{error, info, warn};
+use uuid::Uuid;
+
+use crate::{AppState, db};
+use crate::entity::webhook;
+use crate::entity::prelude::DbWebhook;
+use crate::events::{ClientEvent, ClientEventKind};
+use crate::result::HEError;
+
+/// Delivers every client event to the webhooks subscribed to it, each delivery in its own task
+/// so that a slow or failing endpoint does not hold up the others.
+pub fn spawn_webhook_dispatcher(state: AppState, max_attempts: u32, timeout: Duration) -> Result<(), HEError> {
+    let http_client = reqwest::Client::builder()
+        .timeout(timeout)
+        .build()
+        .map_err(|e| HEError::Message(format!("Error building the webhook HTTP client: {}", e)))?;
+    let mut events = state.events.subscribe();
+    tokio::spawn(async move {
+        loop {
+            let event = match events.recv().await {
+                Ok(event) => event,
+                Err(RecvError::Lagged(skipped)) => {
+                    warn!("Webhook dispatcher fell behind, {} events were not delivered", skipped);
+                    continue;
+                }
+                Err(RecvError::Closed) => break,
+            };
+            let webhooks = match db::webhook::find_webhooks(&state.db).await {
+                Ok(webhooks) => webhooks,
+                Err(e) => {
+                    error!("Failed to find webhooks for event {}: {:?}", event.kind().name(), e);
+                    continue;
+                }
+            };
+            for webhook in webhooks.into_iter().filter(|webhook| is_subscribed(webhook, event.kind())) {
+                let state = state.clone();
+                let http_client = http_client.clone();
+                let event = event.clone();
+                tokio::spawn(async move {
+                    deliver(&webhook, &event, &http_client, max_attempts, &state).await;
+                });
+            }
+        }
+    });
+    Ok(())
+}
+
+/// A webhook without any event in its filter is subscribed to all of them
+fn is_subscribed(webhook: &webhook::Model, kind: ClientEventKind) -> bool {
+    let events: Vec<ClientEventKind> = serde_json::from_value(webhook.events.clone()).unwrap_or_default();
+    events.is_empty() || events.contains(&kind)
+}
+
+/// POSTs the event to the webhook, retrying with exponentially growing delays on network errors,
+/// 429 and 5xx responses. Every attempt is logged in the database.
+async fn deliver(webhook: &webhook::Model, event: &ClientEvent, http_client: &reqwest::Client, max_attempts: u32, state: &AppState) {
+    let payload = match serde_json::to_value(event) {
+        Ok(payload) => payload,
+        Err(e) => {
+            error!("Error serializing event {}: {}", event.kind().name(), e);
+            return;
+        }
+    };
+    let body = payload.to_string();
+    let delivery_id = Uuid::new_v4();
+    let mut delay = Duration::from_secs(1);
+    for attempt in 1..=max_attempts.max(1) {
+        let mut request = http_client.post(&webhook.url)
+            .header("Content-Type", "application/json")
+            .header("X-Host-Exposer-Event", event.kind().name())
+            .header("X-Host-Exposer-Delivery", delivery_id.to_string())
+            .body(body.clone());
+        if let Some(secret) = &webhook.secret {
+            request = request.header("X-Host-Exposer-Signature", format!("sha256={}", sign(secret, &body)));
+        }
+        let (status_code, error, retryable) = match request.send().await {
+            Ok(response) if response.status().is_success() => (Some(response.status().as_u16()), None, false),
+            Ok(response) => {
+                let status = response.status();
+                let retryable = status.is_server_error() || status == reqwest::StatusCode::TOO_MANY_REQUESTS;
+                (Some(status.as_u16()), Some(format!("endpoint responded with {}", status)), retryable)
+            }
+            Err(e) => (None, Some(e.to_string()), true),
+        };
+        if let Err(e) = db::webhook::record_delivery(
+            &webhook.id,
+            event.kind(),
+            payload.clone(),
+            attempt as i32,
+            status_code.map(i32::from),
+            error.clone(),
+            &state.db,
+            &state.default_offset,
+        ).await {
+            error!("Failed to record delivery to webhook {}: {:?}", &webhook.id, e);
+        }
+        match error {
+            None => {
+                info!("Delivered event {} to webhook {}", event.kind().name(), &webhook.url);
+                return;
+            }
+            Some(error) if retryable && attempt < max_attempts => {
+                warn!("Delivering event {} to webhook {} failed (attempt {}): {}, retrying in {:?}",
+                    event.kind().name(), &webhook.url, attempt, error, delay);
+                tokio::time::sleep(delay).await;
+                delay *= 2;
+            }
+            Some(error) => {
+                error!("Giving up delivering event {} to webhook {} after {} attempts: {}",
+                    event.kind().name(), &webhook.url, attempt, error);
+                return;
+            }
+        }
+    }
+}
+
+/// Hex HMAC-SHA256 of the body, letting the receiver check that the request comes from us
+fn sign(secret: &str, body: &str) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any length");
+    mac.update(body.as_bytes());
+    mac.finalize().into_bytes().iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+#[derive(Deserialize)]
+pub struct CreateWebhookBody {
+    url: String,
+    /// Key of the HMAC-SHA256 signature sent in the X-Host-Exposer-Signature header
+    #[serde(default)]
+    secret: Option<String>,
+    /// Events to deliver, all of them if empty
+    #[serde(default)]
+    events: Vec<ClientEventKind>,
+    #[serde(default)]
+    description: Option<String>,
+}
+
+pub async fn create_webhook(
+    State(state): State<AppState>,
+    Json(body): Json<CreateWebhookBody>,
+) -> Result<Json<webhook::Model>, HEError> {
+    let url = reqwest::Url::parse(&body.url)
+        .map_err(|e| HEError::BadRequest(format!("invalid webhook URL {}: {}", &body.url, e)))?;
+    if url.scheme() != "http" && url.scheme() != "https" {
+        return Err(HEError::BadRequest("webhook URL must have a scheme of 'http' or 'https'".to_string()));
+    }
+    let webhook = db::webhook::create_webhook(body.url, body.secret, &body.events, body.description, &state.db, &state.default_offset).await?;
+    Ok(Json(webhook))
+}
+
+pub async fn get_webhooks(State(state): State<AppState>) -> Result<Json<Vec<webhook::Model>>, HEError> {
+    Ok(Json(db::webhook::find_webhooks(&state.db).await?))
+}
+
+pub async fn delete_webhook(
+    State(state): State<AppState>,
+    Path(id): Path<Uuid>,
+) -> Result<(), HEError> {
+    if db::webhook::delete_webhook(&id, &state.db).await?.is_none() {
+        return Err(HEError::NotFound(format!("webhook {} not found", id)));
+    }
+    Ok(())
+}
+
+#[derive(Deserialize)]
+pub struct WebhookDeliveriesQuery {
+    /// Page number starting from 0
+    #[serde(default)]
+    page: u64,
+    #[serde(default = "default_deliveries_page_size")]
+    page_size: u64,
+}
+
+fn default_deliveries_page_size() -> u64 {
+    20
+}
+
+pub async fn get_webhook_deliveries(
+    State(state): State<AppState>,
+    Path(id): Path<Uuid>,
+    Query(query): Query<WebhookDeliveriesQuery>,
+) -> Result<Json<Value>, HEError> {
+    let db = &state.db;
+    if DbWebhook::find_by_id(id).one(db).await?.is_none() {
+        return Err(HEError::NotFound(format!("webhook {} not found", id)));
+    }
+    let page_size = query.page_size.clamp(1, 100);
+    let (entries, total) = db::webhook::find_deliveries(&id, query.page, page_size, db).await?;
+    Ok(Json(json!({
+        "total": total,
+        "page": query.page,
+        "page_size": page_size,
+        "entries": entries
+    })))
+}